@@ -3,6 +3,11 @@ const IMAGE_FILE_TYPE: FileSpec = FileSpec::new("Images", &["bmp", "png", "gif",
 
 pub(crate) const FILE_EXIT_ACTION: Selector = Selector::new("menu-exit-action");
 pub(crate) const GENERATE_TILES: Selector = Selector::new("generate-tiles-action");
+pub(crate) const CANCEL_GENERATE: Selector = Selector::new("cancel-generate-action");
+
+pub(crate) const SET_QUALITY_FAST: Selector = Selector::new("set-quality-fast-action");
+pub(crate) const SET_QUALITY_BALANCED: Selector = Selector::new("set-quality-balanced-action");
+pub(crate) const SET_QUALITY_HIGH: Selector = Selector::new("set-quality-high-action");
 
 pub(crate) const TRIGGER_PROGRESS: Selector = Selector::new("trigger-progress-action");
 
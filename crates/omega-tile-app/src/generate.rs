@@ -1,6 +1,9 @@
 use crate::Error;
 use omega_tile;
-use omega_tile::{ts, SampleMode, WTileSet, WTileVariation};
+use omega_tile::{
+    ts, CancelToken, Channels, ColorOutput, MemoryCache, OmegaTile, SynthesisParams, WTileSet,
+    WTileVariation,
+};
 use std::path::Path;
 use ts::image::{DynamicImage, GenericImage, GenericImageView};
 
@@ -17,13 +20,25 @@ pub struct GenerateOptions {
     pub variation: WTileVariation,
     pub size: u32,
     pub seed: u64,
+    /// Color channels of the tiles and of the saved tile set
+    pub color: ColorOutput,
+    pub synthesis: SynthesisParams,
+    pub cache: MemoryCache,
 }
 
+/// A finished tile, with its index in the set
+type TileData = (usize, Arc<DynamicImage>);
+
 #[must_use]
 pub struct Handle {
     inner: RefCell<Option<thread::JoinHandle<Result<PathBuf, Error>>>>,
     rx: Arc<Receiver<Arc<String>>>,
     last: RefCell<HandleResult<String>>,
+    cancel: CancelToken,
+    tiles_rx: Receiver<TileData>,
+    tile_count: usize,
+    /// Tile set with the tiles finished so far
+    preview: RefCell<Option<Arc<DynamicImage>>>,
 }
 
 #[derive(Clone)]
@@ -34,6 +49,26 @@ pub enum HandleResult<T> {
 }
 
 impl Handle {
+    /// Stop the generation, it then finishes with `omega_tile::Error::Cancelled`
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Tile set with the tiles finished so far, `None` before the first one
+    pub fn preview(&self) -> Option<Arc<DynamicImage>> {
+        let mut preview = self.preview.borrow_mut();
+        for (id, tile) in self.tiles_rx.try_iter() {
+            let (w, h) = tile.dimensions();
+            let n = nearest_sqrt(self.tile_count as u32);
+            let img =
+                preview.get_or_insert_with(|| Arc::new(DynamicImage::new_rgba8(w * n, h * n)));
+            // A retried tile comes again with the same id, and replaces the previous one
+            let (x, y) = (id as u32 % n, id as u32 / n);
+            Arc::make_mut(img).copy_from(&tile.view(0, 0, w, h), x * w, y * h);
+        }
+        preview.clone()
+    }
+
     pub fn get(&self) -> HandleResult<String> {
         let last = self.last.borrow().clone();
 
@@ -61,6 +96,7 @@ impl Handle {
 
 struct DummyReport {
     tx: Arc<Sender<Arc<String>>>,
+    tiles: Sender<TileData>,
 }
 
 impl ts::GeneratorProgress for DummyProgress {
@@ -92,28 +128,39 @@ impl omega_tile::Report for DummyReport {
     ) -> Box<dyn ts::GeneratorProgress> {
         Box::new(DummyProgress { section, tx: self.tx.clone() })
     }
+
+    fn tile_finished(&mut self, tile: &omega_tile::FinishedTile) {
+        // The handle may already be dropped
+        let _ = self.tiles.send((tile.id, tile.tiles[0].img.clone()));
+    }
 }
 
 pub fn generate(input: &Path, output: &Path, opt: &GenerateOptions) -> Handle {
     let input = input.to_owned();
     let output = output.to_owned();
     let opt = opt.clone();
+    let tile_count = opt.variation.tile_count();
 
     let (tx, rx) = channel();
+    let (tiles_tx, tiles_rx) = channel();
+    let cancel = CancelToken::new();
+    let token = cancel.clone();
 
     let t = thread::spawn(move || -> Result<std::path::PathBuf, Error> {
-        let report = DummyReport { tx: Arc::new(tx) };
-
-        // FIXME: Use cache in app ?
-        let (tiles, _) = omega_tile::build(
-            SampleMode::Split,
-            &input.to_string_lossy(),
-            opt.variation,
-            report,
-            None,
-        )?;
-
-        let tileset = build_tileset(&tiles)?;
+        let report = DummyReport { tx: Arc::new(tx), tiles: tiles_tx };
+
+        let results = OmegaTile::builder()
+            .input(Channels::from(&*input.to_string_lossy()))
+            .variation(opt.variation)
+            .color(opt.color)
+            .params(opt.synthesis)
+            .report(report)
+            .cache(opt.cache)
+            .cancel_token(token)
+            .build()?
+            .run()?;
+
+        let tileset = build_tileset(&results.channels[0].tiles, opt.color)?;
         tileset.save(output.clone())?;
         Ok(output)
     });
@@ -122,22 +169,26 @@ pub fn generate(input: &Path, output: &Path, opt: &GenerateOptions) -> Handle {
         inner: RefCell::new(Some(t)),
         rx: Arc::new(rx),
         last: RefCell::new(HandleResult::Ok(String::new())),
+        cancel,
+        tiles_rx,
+        tile_count,
+        preview: RefCell::new(None),
     }
 }
 
-fn build_tileset(tiles: &WTileSet) -> Result<DynamicImage, Error> {
-    fn nearest_sqrt(n: u32) -> u32 {
-        let mut i = 0u32;
-        while n > i * i {
-            i += 1
-        }
-        i
+fn nearest_sqrt(n: u32) -> u32 {
+    let mut i = 0u32;
+    while n > i * i {
+        i += 1
     }
+    i
+}
 
+fn build_tileset(tiles: &WTileSet, color: ColorOutput) -> Result<DynamicImage, Error> {
     let dim = tiles[0].img.dimensions();
     // find nearest square
     let n = nearest_sqrt(tiles.len() as u32);
-    let mut combined = DynamicImage::new_rgb8(dim.0 * n, dim.1 * n);
+    let mut combined = color.new_image(dim.0 * n, dim.1 * n);
 
     let iter = (0..n).flat_map(|y| (0..n).map(move |x| (x, y)));
     let iter = iter.take(tiles.len());
@@ -25,8 +25,15 @@ use omega_tile::ts::image;
 use std::collections::HashSet;
 use std::sync::Arc;
 
+/// Size of the images kept between generations, the least recently used are evicted above it
+const CACHE_SIZE: u64 = 512 * 1024 * 1024;
+
 fn main() {
-    let app = AppData { make: None };
+    let app = AppData {
+        make: None,
+        quality: QualityMode::Balanced,
+        cache: CacheData(omega_tile::MemoryCache::new().with_max_size(CACHE_SIZE)),
+    };
 
     let main_window = WindowDesc::new(ui_builder)
         .title(L!("omega-tile-app-name"))
@@ -53,11 +60,28 @@ fn ui_make_builder() -> impl Widget<Make> {
         None | Some(HandleData::InProgress(_)) => String::new(),
         Some(HandleData::Finish(status)) => match status.as_ref() {
             HandleResult::Ok(_) => String::new(),
-            HandleResult::Fail(_) => "Generating Fail.".to_string(),
+            HandleResult::Fail(err) => match err.downcast_ref::<omega_tile::Error>() {
+                Some(omega_tile::Error::Cancelled) => "Generating Cancelled.".to_string(),
+                _ => "Generating Fail.".to_string(),
+            },
             HandleResult::Success(path) => format!("Done. ({})", path.to_string_lossy()),
         },
     });
 
+    let progress = Flex::column()
+        .with_child(progress::Progress::new().lens(Make::in_progress), 0.0)
+        .with_child(
+            Button::sized(
+                L!("Cancel"),
+                |ctx: &mut EventCtx, _, _| {
+                    ctx.submit_command(commands::CANCEL_GENERATE, ctx.window_id())
+                },
+                200.0,
+                30.0,
+            ),
+            0.0,
+        );
+
     Flex::column()
         .with_child(
             Label::new(|data: &Make, _: &Env| data.path.to_string_lossy().to_string()).center(),
@@ -73,10 +97,7 @@ fn ui_make_builder() -> impl Widget<Make> {
                 },
             })
             .with_branch(ProgressMode::Ready, controls)
-            .with_branch(
-                ProgressMode::InProgress,
-                progress::Progress::new().lens(Make::in_progress),
-            )
+            .with_branch(ProgressMode::InProgress, progress)
             .with_branch(ProgressMode::Finish, finish_label)
             .padding(5.0)
             .center(),
@@ -114,6 +135,17 @@ enum HandleData {
     Finish(Arc<HandleResult<String>>),
 }
 
+/// Images synthesized by the previous generations, shared with the build threads
+#[derive(Clone)]
+struct CacheData(omega_tile::MemoryCache);
+
+impl Data for CacheData {
+    fn same(&self, _other: &Self) -> bool {
+        // Clones share the same images, and nothing is displayed from the cache
+        true
+    }
+}
+
 #[derive(Data, Clone, Eq, PartialEq, Hash)]
 enum ProgressMode {
     Ready,
@@ -128,9 +160,47 @@ struct Make {
     in_progress: Option<HandleData>,
 }
 
+impl Make {
+    /// Whether a generation thread is still running. It is only finished once its
+    /// result is taken from the handle, a cancelled one runs until its synthesis stops.
+    fn is_generating(&self) -> bool {
+        match self.in_progress {
+            Some(HandleData::InProgress(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Refused while a generation runs, even a cancelled one, so two never run at once
+fn check_not_generating(make: Option<&Make>) -> Result<(), Error> {
+    if make.map_or(false, Make::is_generating) {
+        return Err(anyhow::anyhow!("A generation is still running"));
+    }
+    Ok(())
+}
+
+#[derive(Data, Clone, Copy, PartialEq)]
+enum QualityMode {
+    Fast,
+    Balanced,
+    High,
+}
+
+impl From<QualityMode> for omega_tile::Quality {
+    fn from(mode: QualityMode) -> omega_tile::Quality {
+        match mode {
+            QualityMode::Fast => omega_tile::Quality::Fast,
+            QualityMode::Balanced => omega_tile::Quality::Balanced,
+            QualityMode::High => omega_tile::Quality::High,
+        }
+    }
+}
+
 #[derive(Data, Clone, Lens)]
 struct AppData {
     make: Option<Make>,
+    quality: QualityMode,
+    cache: CacheData,
 }
 
 type Error = anyhow::Error;
@@ -149,6 +219,7 @@ fn to_rgba(img: image::DynamicImage) -> image::RgbaImage {
 
 impl AppData {
     fn do_open_image(&mut self, path: &std::path::Path) -> Result<(), Error> {
+        check_not_generating(self.make.as_ref())?;
         let img = image::open(path)?;
         let make = Make {
             img: ImageData(Arc::new(image::DynamicImage::ImageRgba8(to_rgba(img)))),
@@ -160,12 +231,17 @@ impl AppData {
     }
 
     fn do_generate(&mut self, output_path: &std::path::Path) -> Result<(), Error> {
+        let synthesis = omega_tile::SynthesisParams::preset(self.quality.into());
+        check_not_generating(self.make.as_ref())?;
         let make = self.make.as_mut().ok_or_else(|| anyhow::anyhow!("Not in edit mode"))?;
 
         let options = generate::GenerateOptions {
             variation: omega_tile::WTileVariation::V16,
             size: 256,
             seed: 102,
+            color: omega_tile::ColorOutput::Rgb,
+            synthesis,
+            cache: self.cache.0.clone(),
         };
 
         make.in_progress = Some(HandleData::InProgress(Arc::new(generate::generate(
@@ -206,6 +282,16 @@ impl Delegate {
                     ctx.submit_command(commands::TRIGGER_PROGRESS.into(), Some(*id));
                 }
             }
+            &commands::SET_QUALITY_FAST => data.quality = QualityMode::Fast,
+            &commands::SET_QUALITY_BALANCED => data.quality = QualityMode::Balanced,
+            &commands::SET_QUALITY_HIGH => data.quality = QualityMode::High,
+            &commands::CANCEL_GENERATE => {
+                if let Some(Make { in_progress: Some(HandleData::InProgress(handle)), .. }) =
+                    &data.make
+                {
+                    handle.cancel();
+                }
+            }
             &commands::GENERATE_TILES => {
                 ctx.submit_command(commands::generate_tiles_command(), None);
             }
@@ -227,7 +313,8 @@ impl AppDelegate<AppData> for Delegate {
         match event {
             Event::Command(ref cmd) => {
                 if let Err(err) = self.handle_command(data, ctx, cmd) {
-                    eprintln!("Error => {}", err.to_string());
+                    // With the causes, which the messages of the library leave out
+                    eprintln!("Error => {:#}", err);
                 }
             }
 
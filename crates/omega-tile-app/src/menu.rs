@@ -3,13 +3,20 @@ use crate::AppData;
 use druid::{KeyCode, MenuDesc, MenuItem, RawMods};
 
 pub(crate) fn make_menu(app: &AppData) -> MenuDesc<AppData> {
-    MenuDesc::empty().append(file_menu(app))
+    MenuDesc::empty().append(file_menu(app)).append(quality_menu(app))
 }
 
 fn file_menu(_app: &AppData) -> MenuDesc<AppData> {
     MenuDesc::new(L!("menu-file-menu")).append(open()).append_separator().append(exit())
 }
 
+fn quality_menu(_app: &AppData) -> MenuDesc<AppData> {
+    MenuDesc::new(L!("menu-quality-menu"))
+        .append(quality_fast())
+        .append(quality_balanced())
+        .append(quality_high())
+}
+
 macro_rules! register_menu_items {
     ($($name:ident => ($sel:literal, $cmd:expr $(, $mods:ident, $keycode:ident)? )),*) => {
         $(
@@ -23,5 +30,10 @@ macro_rules! register_menu_items {
 register_menu_items! {
     // files
     open => ("menu-file-open", commands::file_open_command(), Ctrl, KeyO),
-    exit => ("menu-file-exit", commands::FILE_EXIT_ACTION, Alt, F4)
+    exit => ("menu-file-exit", commands::FILE_EXIT_ACTION, Alt, F4),
+
+    // quality
+    quality_fast => ("menu-quality-fast", commands::SET_QUALITY_FAST),
+    quality_balanced => ("menu-quality-balanced", commands::SET_QUALITY_BALANCED),
+    quality_high => ("menu-quality-high", commands::SET_QUALITY_HIGH)
 }
@@ -1,5 +1,6 @@
 use crate::generate::HandleResult;
-use crate::HandleData;
+use crate::widgets::Image;
+use crate::{HandleData, ImageData};
 use druid::widget;
 use druid::{
     BoxConstraints, Env, Event, EventCtx, LayoutCtx, PaintCtx, Point, Rect, Size, UpdateCtx,
@@ -7,8 +8,10 @@ use druid::{
 };
 use std::sync::Arc;
 
+/// Progress of a generation, with the tiles finished so far
 pub struct Progress {
     inner: WidgetPod<String, Box<dyn Widget<String>>>,
+    preview: WidgetPod<ImageData, Box<dyn Widget<ImageData>>>,
 }
 
 type DataType = Option<HandleData>;
@@ -26,6 +29,13 @@ fn in_progress(data: &DataType) -> Option<String> {
     }
 }
 
+fn preview(data: &DataType) -> Option<ImageData> {
+    match data.as_ref()? {
+        HandleData::InProgress(it) => it.preview().map(ImageData),
+        HandleData::Finish(_) => None,
+    }
+}
+
 impl Widget<DataType> for Progress {
     fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut DataType, env: &Env) {
         match event {
@@ -72,6 +82,14 @@ impl Widget<DataType> for Progress {
         if let Some(s) = in_progress(data) {
             let size = self.inner.layout(ctx, bc, &s, env);
             self.inner.set_layout_rect(Rect::from_origin_size(Point::ORIGIN, size));
+
+            // The tiles finished so far, below the progress text
+            if let Some(img) = preview(data) {
+                let img_size = self.preview.layout(ctx, bc, &img, env);
+                let origin = Point::new(0.0, size.height);
+                self.preview.set_layout_rect(Rect::from_origin_size(origin, img_size));
+                return Size::new(size.width.max(img_size.width), size.height + img_size.height);
+            }
             size
         } else {
             Size::ZERO
@@ -81,6 +99,9 @@ impl Widget<DataType> for Progress {
         if let Some(s) = in_progress(data) {
             self.inner.paint_with_offset(paint_ctx, &s, env);
         }
+        if let Some(img) = preview(data) {
+            self.preview.paint_with_offset(paint_ctx, &img, env);
+        }
     }
 }
 
@@ -88,6 +109,9 @@ impl Progress {
     pub fn new() -> Progress {
         let f = |data: &String, _: &Env| data.clone();
         let label = widget::Label::<String>::new(f);
-        Progress { inner: WidgetPod::new(label).boxed() }
+        Progress {
+            inner: WidgetPod::new(label).boxed(),
+            preview: WidgetPod::new(Image::new()).boxed(),
+        }
     }
 }
@@ -1,21 +1,43 @@
 mod report;
 
+use conv::ValueInto;
+use imageproc::definitions::Clamp;
 use imageproc::drawing;
-use omega_tile::{ts, Atlas, Cache, Error, SampleMode, WTileSet, WTileVariation};
+use omega_tile::{
+    ts, Atlas, BuildResult, Cache, Channels, ColorOutput, Error, ExamplePool, ExampleSampling,
+    Mask, MaskOptions, MaskShape, OmegaTile, PackedCache, Preprocess, Quality, Reference,
+    RetryPolicy, Rgba16Image, SampleMode, SeamOptions, SynthesisParams, TexelDensity, TileCache,
+    TileLayout, WTileSet, WTileVariation,
+};
 use rusttype::{FontCollection, Scale};
 use std::path::Path;
+use std::time::Duration;
 use structopt::StructOpt;
-use ts::image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+use ts::image::{DynamicImage, GenericImage, GenericImageView, Pixel, Rgba};
 
-use report::SimpleProgressReport;
+use report::ProgressFormat;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "omega-tile", about = "ω-tile generator")]
 enum Command {
-    Clean,
+    Clean {
+        /// Cache directory, instead of `OMEGA_TILE_CACHE_DIR` or the per-user default
+        #[structopt(long)]
+        cache_dir: Option<String>,
+    },
+    /// Show and trim the image cache
+    Cache {
+        /// Cache directory, instead of `OMEGA_TILE_CACHE_DIR` or the per-user default
+        #[structopt(long)]
+        cache_dir: Option<String>,
+
+        #[structopt(subcommand)]
+        cmd: CacheCommand,
+    },
     Build {
-        #[structopt(short, long)]
-        variation: WTileVariation,
+        // Boxed, as the other commands are much smaller
+        #[structopt(flatten)]
+        tile: Box<TileOpts>,
 
         #[structopt(short, long)]
         combined: bool,
@@ -23,15 +45,29 @@ enum Command {
         #[structopt(short, long)]
         print_index: bool,
 
-        input: String,
         size: u32,
 
-        #[structopt(short, long, default_value = "100")]
-        seed: u64,
-
         #[structopt(short, long)]
         number: bool,
     },
+    /// Measure how well the tiles of a set join their neighbours
+    Inspect {
+        /// Tile set image written by `build`, without `--number`, with 8 or 16 bits
+        tileset: String,
+
+        #[structopt(short, long)]
+        variation: WTileVariation,
+
+        /// Samples the tiles were built from, to measure the deviation of the corners
+        #[structopt(long)]
+        samples: Vec<String>,
+
+        /// Number of tiles in the worst offender list
+        #[structopt(long, default_value = "5")]
+        worst: usize,
+    },
+    /// Estimate the feature size of an input and recommend a tile size
+    Analyze { input: String },
     TestSet {
         #[structopt(short, long)]
         variation: WTileVariation,
@@ -49,21 +85,350 @@ enum Command {
 
         #[structopt(short, long)]
         number: bool,
+
+        /// How the progress is shown (bars, json)
+        #[structopt(long, default_value = "bars")]
+        progress: ProgressFormat,
+
+        #[structopt(flatten)]
+        cache: CacheOpts,
     },
 }
 
-fn build_combine_img(atlas: &Atlas) -> Result<DynamicImage, Error> {
+#[derive(Debug, StructOpt)]
+enum CacheCommand {
+    /// Number of entries, size and hit rate of the last build
+    Stats,
+    /// List the entries with the version, input and parameters they were built from
+    Ls,
+    /// Remove old entries, and the entries made by another version of omega-tile
+    Prune {
+        /// Remove the entries not used for this long (e.g. `30m`, `12h`, `7d`)
+        #[structopt(long)]
+        older_than: Option<Age>,
+
+        /// Remove the least recently used entries until the cache fits in this many megabytes
+        #[structopt(long)]
+        max_size: Option<u64>,
+    },
+}
+
+/// A duration given as a number followed by `s`, `m`, `h` or `d`
+#[derive(Debug)]
+struct Age(Duration);
+
+impl std::str::FromStr for Age {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || Error::ParseError(format!("Not a valid age: {}", s));
+        let unit = match s.chars().last() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            _ => return Err(err()),
+        };
+        let n: u64 = s[..s.len() - 1].parse().map_err(|_| err())?;
+        Ok(Age(Duration::from_secs(n * unit)))
+    }
+}
+
+fn open_cache(dir: &Option<String>) -> Cache {
+    match dir {
+        Some(dir) => Cache::with_dir(dir),
+        None => Cache::new(),
+    }
+}
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// Where synthesized images are cached
+#[derive(Debug, StructOpt)]
+struct CacheOpts {
+    /// Cache directory, instead of `OMEGA_TILE_CACHE_DIR` or the per-user default
+    #[structopt(long)]
+    cache_dir: Option<String>,
+
+    /// Single file cache, which can be copied to another machine, instead of the cache directory
+    #[structopt(long, conflicts_with = "cache_dir")]
+    cache_file: Option<String>,
+
+    /// Do not read or write cached images
+    #[structopt(long)]
+    no_cache: bool,
+
+    /// Evict the least recently used images when the cache grows above this many megabytes
+    #[structopt(long)]
+    cache_max_size: Option<u64>,
+}
+
+impl CacheOpts {
+    fn cache(&self) -> Result<Option<Box<dyn TileCache>>, Error> {
+        if self.no_cache {
+            return Ok(None);
+        }
+        if let Some(file) = &self.cache_file {
+            return Ok(Some(Box::new(PackedCache::open(file)?)));
+        }
+        let cache = open_cache(&self.cache_dir);
+        let cache = match self.cache_max_size {
+            Some(size) => cache.with_max_size(size * MEGABYTE),
+            None => cache,
+        };
+        Ok(Some(Box::new(cache)))
+    }
+}
+
+/// Options that decide which tiles are built from the input
+#[derive(Debug, StructOpt)]
+struct TileOpts {
+    #[structopt(short, long)]
+    variation: WTileVariation,
+
+    input: String,
+
+    #[structopt(short, long, default_value = "100")]
+    seed: u64,
+
+    /// Additional channels aligned with the input (e.g. `normal=imgs/grass_normal.png`)
+    #[structopt(long)]
+    channel: Vec<String>,
+
+    /// Crop the input to the largest centered square
+    #[structopt(long)]
+    square: bool,
+
+    /// Crop the input so the tiles have even dimensions
+    #[structopt(long)]
+    even: bool,
+
+    /// Resize the input so the tiles have this width, which must be even, keeping the aspect ratio
+    #[structopt(long)]
+    tile_size: Option<u32>,
+
+    /// Width in meters of the area shown by the input, to resize it with `--texels-per-meter`
+    #[structopt(long)]
+    source_width: Option<f32>,
+
+    /// Resize the input to this texel density, needs `--source-width`
+    #[structopt(long)]
+    texels_per_meter: Option<f32>,
+
+    /// Make the input seamless before building the samples
+    #[structopt(long)]
+    seamless: bool,
+
+    /// Color channels of the output (rgb, rgba)
+    #[structopt(long, default_value = "rgb")]
+    color: ColorOutput,
+
+    /// Shape of the corner regions kept from the samples (circle, diamond, square)
+    #[structopt(long, default_value = "circle")]
+    mask: MaskShape,
+
+    /// Size of the corner regions, relative to half of the tile
+    #[structopt(long, default_value = "1.0")]
+    mask_radius: f32,
+
+    /// Width in pixels of the soft mask border
+    #[structopt(long, default_value = "0")]
+    mask_feather: u32,
+
+    /// Amplitude of the noise applied to the mask border
+    #[structopt(long, default_value = "0.0")]
+    mask_noise: f32,
+
+    /// Use a mask image instead of a generated shape
+    #[structopt(long)]
+    mask_image: Option<String>,
+
+    /// Compute the mask of each tile with a minimal cost cut through the samples
+    #[structopt(long)]
+    seam: bool,
+
+    /// Half width of the band always synthesized around the sample seams
+    #[structopt(long, default_value = "0.125")]
+    seam_margin: f32,
+
+    /// How strongly the cut is pulled towards the sample seams
+    #[structopt(long, default_value = "1.0")]
+    seam_keep: f32,
+
+    #[structopt(flatten)]
+    synthesis: SynthesisOpts,
+
+    /// Also copy patches from the whole input image when synthesizing tiles
+    #[structopt(long)]
+    input_example: bool,
+
+    /// Additional images to copy patches from when synthesizing tiles
+    #[structopt(long)]
+    reference: Vec<String>,
+
+    /// Synthesize again the tiles with a quality score above this threshold
+    #[structopt(long)]
+    retry_threshold: Option<f64>,
+
+    /// Maximum number of new attempts for each tile above the threshold
+    #[structopt(long, default_value = "3")]
+    retry_attempts: u32,
+
+    /// How the progress is shown (bars, json)
+    #[structopt(long, default_value = "bars")]
+    progress: ProgressFormat,
+
+    #[structopt(flatten)]
+    cache: CacheOpts,
+}
+
+impl TileOpts {
+    /// Name used as prefix of the output files
+    fn output(&self) -> Result<&str, Error> {
+        Path::new(&self.input)
+            .file_stem()
+            .ok_or_else(|| Error::InvalidInput(format!("No file name in {}", self.input)))?
+            .to_str()
+            .ok_or_else(|| Error::InvalidInput(format!("Not a valid file name: {}", self.input)))
+    }
+
+    fn build(&self) -> Result<BuildResult, Error> {
+        let mask = match &self.mask_image {
+            Some(path) => Mask::open(path)?,
+            None if self.seam => {
+                Mask::Seam(SeamOptions { margin: self.seam_margin, keep: self.seam_keep })
+            }
+            None => Mask::Shape(MaskOptions {
+                shape: self.mask,
+                radius: self.mask_radius,
+                feather: self.mask_feather,
+                noise: self.mask_noise,
+                seed: self.seed,
+            }),
+        };
+
+        let pool = ExamplePool {
+            input: if self.input_example { Some(ExampleSampling::All) } else { None },
+            references: self
+                .reference
+                .iter()
+                .map(|path| Reference::open(path, ExampleSampling::All))
+                .collect::<Result<_, _>>()?,
+        };
+
+        let mut channels = Channels::new("main", &self.input);
+        for it in &self.channel {
+            let mut parts = it.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(path)) => channels = channels.with_channel(name, path),
+                _ => Err(Error::ParseError(format!("Not a valid channel: {}", it)))?,
+            }
+        }
+
+        let density = match (self.source_width, self.texels_per_meter) {
+            (Some(source_width), Some(texels_per_meter)) => {
+                Some(TexelDensity { source_width, texels_per_meter })
+            }
+            (None, None) => None,
+            _ => Err(Error::InvalidInput(
+                "--source-width and --texels-per-meter must be used together".into(),
+            ))?,
+        };
+
+        let preprocess = Preprocess {
+            square: self.square,
+            even: self.even,
+            tile_size: self.tile_size,
+            density,
+            seamless: self.seamless,
+        };
+
+        let mut builder = OmegaTile::builder()
+            .input(channels)
+            .mode(SampleMode::Split)
+            .preprocess(preprocess)
+            .color(self.color)
+            .variation(self.variation)
+            .mask(mask)
+            .params(self.synthesis.to_params())
+            .example_pool(pool)
+            .report(self.progress.report());
+        if let Some(threshold) = self.retry_threshold {
+            builder = builder.retry(RetryPolicy { threshold, attempts: self.retry_attempts });
+        }
+        if let Some(cache) = self.cache.cache()? {
+            builder = builder.cache(cache);
+        }
+
+        builder.build()?.run()
+    }
+}
+
+/// Texture synthesis tuning, on top of a quality preset
+#[derive(Debug, StructOpt)]
+struct SynthesisOpts {
+    /// Synthesis quality preset (fast, balanced, high)
+    #[structopt(long, default_value = "balanced")]
+    quality: Quality,
+
+    #[structopt(long)]
+    nearest_neighbors: Option<u32>,
+
+    #[structopt(long)]
+    random_sample_locations: Option<u64>,
+
+    #[structopt(long)]
+    backtrack_stages: Option<u32>,
+
+    #[structopt(long)]
+    backtrack_percent: Option<f32>,
+
+    #[structopt(long)]
+    cauchy_dispersion: Option<f32>,
+
+    #[structopt(long)]
+    guide_alpha: Option<f32>,
+}
+
+impl SynthesisOpts {
+    fn to_params(&self) -> SynthesisParams {
+        let mut params = SynthesisParams::preset(self.quality);
+        if let Some(it) = self.nearest_neighbors {
+            params.nearest_neighbors = it;
+        }
+        if let Some(it) = self.random_sample_locations {
+            params.random_sample_locations = it;
+        }
+        if let Some(it) = self.backtrack_stages {
+            params.backtrack_stages = it;
+        }
+        if let Some(it) = self.backtrack_percent {
+            params.backtrack_percent = it;
+        }
+        if let Some(it) = self.cauchy_dispersion {
+            params.cauchy_dispersion = it;
+        }
+        if let Some(it) = self.guide_alpha {
+            params.guide_alpha = it;
+        }
+        params
+    }
+}
+
+fn build_combine_img<I, T>(atlas: &Atlas, tiles: &[&T], mut combined: I) -> Result<I, Error>
+where
+    I: GenericImage,
+    T: GenericImageView<Pixel = I::Pixel>,
+{
     let dim = atlas.tile_dimensions();
-    let full_dim = atlas.dimensions();
-    let mut combined = DynamicImage::new_rgb8(full_dim.0, full_dim.1);
 
     let n = atlas.size() as i32;
     for y in 0..n {
         for x in 0..n {
-            let (_id, w) = atlas.get(x, y).expect("Altas is not completed");
+            let (id, _) = atlas.get(x, y).ok_or(Error::AtlasUnsatisfiable((x as u32, y as u32)))?;
 
             if !combined.copy_from(
-                &w.img.view(0, 0, dim.0, dim.1),
+                &tiles[id].view(0, 0, dim.0, dim.1),
                 (x as u32) * dim.0,
                 (y as u32) * dim.1,
             ) {
@@ -74,33 +439,30 @@ fn build_combine_img(atlas: &Atlas) -> Result<DynamicImage, Error> {
     Ok(combined)
 }
 
-fn draw_number(
-    image: &mut DynamicImage,
+fn draw_number<I>(
+    image: &mut I,
+    color: I::Pixel,
     n: u32,
     x: u32,
     y: u32,
     w: u32,
     h: u32,
-) -> Result<(), Error> {
+) -> Result<(), Error>
+where
+    I: GenericImage,
+    <I::Pixel as Pixel>::Subpixel: ValueInto<f32> + Clamp<f32>,
+{
     let font = Vec::from(include_bytes!("DejaVuSans.ttf") as &[u8]);
     let font = FontCollection::from_bytes(font).unwrap().into_font().unwrap();
 
     let height = 12.4;
     let scale = Scale { x: height * 2.0, y: height };
 
-    drawing::draw_text_mut(
-        image,
-        Rgba([0u8, 0u8, 255u8, 255u8]),
-        x,
-        y,
-        scale,
-        &font,
-        &n.to_string(),
-    );
+    drawing::draw_text_mut(image, color, x, y, scale, &font, &n.to_string());
 
     drawing::draw_text_mut(
         image,
-        Rgba([0u8, 0u8, 255u8, 255u8]),
+        color,
         x + w - (height.ceil() as u32) * (n.to_string().len() as u32),
         y + h - (height.ceil() as u32),
         scale,
@@ -111,146 +473,295 @@ fn draw_number(
     Ok(())
 }
 
-fn build_tileset(tiles: &WTileSet, with_number: bool) -> Result<DynamicImage, Error> {
-    fn nearest_sqrt(n: u32) -> u32 {
-        let mut i = 0u32;
-        while n > i * i {
-            i += 1
-        }
-        i
+fn nearest_sqrt(n: u32) -> u32 {
+    let mut i = 0u32;
+    while n > i * i {
+        i += 1
     }
+    i
+}
 
-    let dim = tiles[0].img.dimensions();
-    // find nearest square
-    let n = nearest_sqrt(tiles.len() as u32);
-    let mut combined = DynamicImage::new_rgb8(dim.0 * n, dim.1 * n);
+/// Size of the tile set image, in tiles along each side
+fn tileset_size(count: usize) -> u32 {
+    nearest_sqrt(count as u32)
+}
+
+/// Draw the tiles on a grid of `tileset_size` cells, `number` is the color of the
+/// tile numbers if they are drawn
+fn build_tileset<I, T>(tiles: &[&T], number: Option<I::Pixel>, mut combined: I) -> Result<I, Error>
+where
+    I: GenericImage,
+    T: GenericImageView<Pixel = I::Pixel>,
+    <I::Pixel as Pixel>::Subpixel: ValueInto<f32> + Clamp<f32>,
+{
+    let dim = tiles[0].dimensions();
+    let n = tileset_size(tiles.len());
 
     let iter = (0..n).flat_map(|y| (0..n).map(move |x| (x, y)));
     let iter = iter.take(tiles.len());
 
     for (i, (x, y)) in iter.enumerate() {
-        if !combined.copy_from(
-            &tiles[i].img.view(0, 0, dim.0, dim.1),
-            (x as u32) * dim.0,
-            (y as u32) * dim.1,
-        ) {
+        if !combined.copy_from(&tiles[i].view(0, 0, dim.0, dim.1), x * dim.0, y * dim.1) {
             Err(Error::SizeMismatch)?;
         }
 
-        if with_number {
-            draw_number(
-                &mut combined,
-                i as u32,
-                (x as u32) * dim.0,
-                (y as u32) * dim.1,
-                dim.0,
-                dim.1,
-            )?;
+        if let Some(color) = number {
+            draw_number(&mut combined, color, i as u32, x * dim.0, y * dim.1, dim.0, dim.1)?;
         }
     }
 
     Ok(combined)
 }
 
+/// Blue, the color of the tile numbers
+const NUMBER: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+/// Tile set image of a channel, with 8 bits per channel
+fn tileset_image(
+    tiles: &WTileSet,
+    with_number: bool,
+    color: ColorOutput,
+) -> Result<DynamicImage, Error> {
+    let imgs: Vec<&DynamicImage> = tiles.iter().map(|it| &*it.img).collect();
+    let n = tileset_size(imgs.len());
+    let dim = imgs[0].dimensions();
+    let number = if with_number { Some(NUMBER) } else { None };
+    build_tileset(&imgs, number, color.new_image(dim.0 * n, dim.1 * n))
+}
+
+/// Combined image of a channel, with 8 bits per channel
+fn combined_image(
+    atlas: &Atlas,
+    tiles: &WTileSet,
+    color: ColorOutput,
+) -> Result<DynamicImage, Error> {
+    let imgs: Vec<&DynamicImage> = tiles.iter().map(|it| &*it.img).collect();
+    let (w, h) = atlas.dimensions();
+    build_combine_img(atlas, &imgs, color.new_image(w, h))
+}
+
+/// Cut a tile set image made by `build_tileset` back into its tiles
+fn open_tileset(path: &str, variation: WTileVariation) -> Result<WTileSet, Error> {
+    let img = omega_tile::open_reduced_image(path)?;
+    let layout = TileLayout::from(variation);
+    let count = layout.tile_count() as u32;
+    let n = tileset_size(count as usize);
+
+    let (w, h) = img.dimensions();
+    if w % n != 0 || h % n != 0 {
+        return Err(Error::InvalidInput(format!(
+            "A {} tile set is a grid of {}x{} tiles, {} is {}x{}",
+            variation, n, n, path, w, h
+        )));
+    }
+    let dim = (w / n, h / n);
+
+    let imgs = (0..count)
+        .map(|i| img.view((i % n) * dim.0, (i / n) * dim.1, dim.0, dim.1).to_image())
+        .map(DynamicImage::ImageRgba8)
+        .collect();
+    omega_tile::tiles_from_images(imgs, &layout)
+}
+
 fn main() -> Result<(), Error> {
     let cmd = Command::from_args();
 
     match cmd {
-        Command::Clean => {
-            Cache::new().clear_cache();
+        Command::Clean { cache_dir } => {
+            open_cache(&cache_dir).clear_cache()?;
             println!("Image cache is clean.");
         }
-        Command::Build { input, size, variation, combined, print_index, seed, number } => {
-            let output = Path::new(&input)
-                .file_stem()
-                .ok_or_else(|| {
-                    std::io::Error::new(std::io::ErrorKind::NotFound, "Input file not found")
-                })?
-                .to_str()
-                .ok_or_else(|| {
-                    std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "Input file name is not valid",
-                    )
-                })?;
-
-            let (tiles, samples) = omega_tile::build(
-                SampleMode::Split,
-                &input,
-                variation,
-                SimpleProgressReport::new(),
-                Some(Cache::new()),
-            )?;
-            for (i, it) in samples.iter().enumerate() {
-                let name = format!("out/{}_samples{}.png", output, i + 1);
-                it.save(&name).map_err(|e| {
-                    Error::General((
-                        Box::new(e.into()),
-                        format!("Fail to save samples to {}", name),
-                    ))
-                })?;
+        Command::Cache { cache_dir, cmd } => {
+            let cache = open_cache(&cache_dir);
+            match cmd {
+                CacheCommand::Stats => {
+                    let stats = cache.stats()?;
+                    println!("Directory: {}", cache.dir().display());
+                    println!("Entries: {}", stats.entries);
+                    println!("Size: {:.1} MB", stats.bytes as f64 / MEGABYTE as f64);
+                    match stats.hit_rate() {
+                        Some(rate) => println!("Hit rate of the last build: {:.1}%", rate * 100.0),
+                        None => println!("Hit rate of the last build: -"),
+                    }
+                }
+                CacheCommand::Ls => {
+                    for entry in cache.entries()? {
+                        let name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+                        let outdated = if entry.compatible { "" } else { " (outdated)" };
+                        println!(
+                            "{} {:>10} {}{} {} {}",
+                            name,
+                            entry.bytes,
+                            entry.version.as_ref().map_or("-", String::as_str),
+                            outdated,
+                            entry.source.as_ref().map_or("-", String::as_str),
+                            entry.key.as_ref().map_or("-", String::as_str)
+                        );
+                    }
+                }
+                CacheCommand::Prune { older_than, max_size } => {
+                    let mut n = cache.remove_incompatible()?;
+                    if let Some(Age(age)) = older_than {
+                        n += cache.prune_older_than(age)?;
+                    }
+                    if let Some(size) = max_size {
+                        n += cache.evict_to(size * MEGABYTE)?;
+                    }
+                    println!("Removed {} cache entries.", n);
+                }
+            }
+        }
+        Command::Build { tile, size, combined, print_index, number } => {
+            let output = tile.output()?;
+            let (variation, seed, color, progress) =
+                (tile.variation, tile.seed, tile.color, tile.progress);
+            let BuildResult { channels: results, retries } = tile.build()?;
+            for it in &retries {
+                progress.message(&format!(
+                    "Tile {} synthesized again {} time(s), score {:.2} -> {:.2}",
+                    it.id, it.attempts, it.initial_score, it.score
+                ));
             }
 
+            // Every channel shares the same layout, so one atlas fits all of them
             let combined_size = size;
-            let atlas = omega_tile::build_atlas(&tiles, combined_size, seed);
+            let atlas = omega_tile::build_atlas(&results[0].tiles, combined_size, seed)?;
 
-            if combined {
-                let combined = build_combine_img(&atlas)?;
-                combined.save(format!(
+            for result in &results {
+                let output = if results.len() == 1 {
+                    output.to_string()
+                } else {
+                    format!("{}_{}", output, result.name)
+                };
+
+                let samples = (1..).map(|i| format!("out/{}_samples{}.png", output, i));
+                let combined_name = format!(
                     "out/{}_combined_{}x{}_{}_{}.png",
                     output, combined_size, combined_size, variation, seed
-                ))?;
+                );
+                let tileset_name = format!(
+                    "out/{}_tileset_{}x{}_{}_{}.png",
+                    output, combined_size, combined_size, variation, seed
+                );
+
+                // 16 bits inputs keep their precision in every output image
+                if let Some(deep) = &result.deep {
+                    for (it, name) in deep.samples.iter().zip(samples) {
+                        omega_tile::save_deep_image(it, color, &name)?;
+                    }
+
+                    let tiles: Vec<&Rgba16Image> = deep.tiles.iter().collect();
+                    let dim = tiles[0].dimensions();
+                    if combined {
+                        let (w, h) = atlas.dimensions();
+                        let img = build_combine_img(&atlas, &tiles, Rgba16Image::new(w, h))?;
+                        omega_tile::save_deep_image(&img, color, &combined_name)?;
+                    }
+
+                    let n = tileset_size(tiles.len());
+                    let number = if number { Some(Rgba([0, 0, u16::MAX, u16::MAX])) } else { None };
+                    let img =
+                        build_tileset(&tiles, number, Rgba16Image::new(dim.0 * n, dim.1 * n))?;
+                    omega_tile::save_deep_image(&img, color, &tileset_name)?;
+                    continue;
+                }
+
+                for (it, name) in result.samples.iter().zip(samples) {
+                    it.save(&name).map_err(|e| {
+                        std::io::Error::new(
+                            e.kind(),
+                            format!("Fail to save samples to {}: {}", name, e),
+                        )
+                    })?;
+                }
+
+                if combined {
+                    combined_image(&atlas, &result.tiles, color)?.save(&combined_name)?;
+                }
+
+                tileset_image(&result.tiles, number, color)?.save(&tileset_name)?;
             }
 
-            let indices = atlas.build_indices();
+            let indices = atlas.build_indices()?;
             indices.save(format!(
                 "out/{}_indices_{}x{}_{}_{}.bmp",
                 output, combined_size, combined_size, variation, seed
             ))?;
 
-            let tileset = build_tileset(&tiles, number)?;
-            tileset.save(format!(
-                "out/{}_tileset_{}x{}_{}_{}.png",
-                output, combined_size, combined_size, variation, seed
-            ))?;
-
             if print_index {
-                println!("{}", atlas);
+                progress.message(&atlas.to_string());
             }
         }
-        Command::TestSet { size, combined, variation, print_index, seed, number } => {
+        Command::Inspect { tileset, variation, samples, worst } => {
+            let tiles = open_tileset(&tileset, variation)?;
+            let samples = samples
+                .iter()
+                .map(omega_tile::open_reduced_image)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let report = if samples.is_empty() {
+                omega_tile::inspect(&tiles, None)?
+            } else if samples.len() == 4 {
+                omega_tile::inspect(&tiles, Some(&samples[..]))?
+            } else {
+                return Err(Error::InvalidInput(format!(
+                    "Give the 4 samples of the tile set, got {}",
+                    samples.len()
+                )));
+            };
+            println!("{}", report);
+
+            println!("Worst tiles:");
+            for t in report.worst(worst) {
+                println!("{:>4} (score {:.2})", t.id, t.score());
+            }
+        }
+        Command::Analyze { input } => {
+            print!("{}", omega_tile::analyze(&input)?);
+        }
+        Command::TestSet {
+            size,
+            combined,
+            variation,
+            print_index,
+            seed,
+            number,
+            progress,
+            cache,
+        } => {
             let output = "test_set";
-            let tiles = omega_tile::build_testset(
-                variation,
-                SimpleProgressReport::new(),
-                Some(Cache::new()),
-            )?;
+            let mut builder =
+                OmegaTile::builder().test_samples().variation(variation).report(progress.report());
+            if let Some(cache) = cache.cache()? {
+                builder = builder.cache(cache);
+            }
+            let tiles = builder.build()?.run()?.channels.remove(0).tiles;
 
             let combined_size = size;
-            let atlas = omega_tile::build_atlas(&tiles, combined_size, seed);
+            let atlas = omega_tile::build_atlas(&tiles, combined_size, seed)?;
 
             if combined {
-                let combined = build_combine_img(&atlas)?;
+                let combined = combined_image(&atlas, &tiles, ColorOutput::Rgb)?;
                 combined.save(format!(
                     "out/{}_combined_{}x{}_{}_{}.png",
                     output, combined_size, combined_size, variation, seed
                 ))?;
             }
 
-            let indices = atlas.build_indices();
+            let indices = atlas.build_indices()?;
             indices.save(format!(
                 "out/{}_indices_{}x{}_{}_{}.bmp",
                 output, combined_size, combined_size, variation, seed
             ))?;
 
-            let tileset = build_tileset(&tiles, number)?;
+            let tileset = tileset_image(&tiles, number, ColorOutput::Rgb)?;
             tileset.save(format!(
                 "out/{}_tileset_{}x{}_{}_{}.png",
                 output, combined_size, combined_size, variation, seed
             ))?;
 
             if print_index {
-                println!("{}", atlas);
+                progress.message(&atlas.to_string());
             }
         }
     }
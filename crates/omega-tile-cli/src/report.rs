@@ -1,5 +1,5 @@
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use omega_tile::{Report, ReportSection};
+use omega_tile::{BuildEvent, Error, Report, ReportSection};
 use std::cell::RefCell;
 use std::rc::Rc;
 use texture_synthesis as ts;
@@ -72,9 +72,9 @@ impl Drop for SimpleProgressContext {
     fn drop(&mut self) {
         self.total_pb.finish();
         self.stage_pb.finish();
-        self.mthread.take().map(|t| {
+        if let Some(t) = self.mthread.take() {
             let _ = t.join();
-        });
+        }
     }
 }
 
@@ -98,3 +98,215 @@ impl ts::GeneratorProgress for SubProgressReport {
         ctx.stage_pb.set_position(update.stage.current as u64);
     }
 }
+
+/// How the progress is shown
+#[derive(Debug, Copy, Clone)]
+pub enum ProgressFormat {
+    /// Progress bars on the terminal
+    Bars,
+    /// One JSON object per line on stdout
+    Json,
+}
+
+impl std::str::FromStr for ProgressFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bars" => Ok(ProgressFormat::Bars),
+            "json" => Ok(ProgressFormat::Json),
+            _ => Err(Error::ParseError("Not a valid progress format".into())),
+        }
+    }
+}
+
+impl ProgressFormat {
+    pub fn report(self) -> Box<dyn Report> {
+        match self {
+            ProgressFormat::Bars => Box::new(SimpleProgressReport::new()),
+            ProgressFormat::Json => Box::new(JsonProgressReport),
+        }
+    }
+
+    /// Print a message, on stderr with JSON progress so stdout only holds JSON lines
+    pub fn message(self, message: &str) {
+        match self {
+            ProgressFormat::Bars => println!("{}", message),
+            ProgressFormat::Json => eprintln!("{}", message),
+        }
+    }
+}
+
+/// Writes the build events and the synthesis progress as JSON lines
+pub struct JsonProgressReport;
+
+fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+fn json_corners(corners: &[usize; 4]) -> String {
+    format!("[{},{},{},{}]", corners[0], corners[1], corners[2], corners[3])
+}
+
+impl Report for JsonProgressReport {
+    fn sub_progress_bar(&mut self, section: ReportSection) -> Box<dyn ts::GeneratorProgress> {
+        Box::new(JsonSubProgress { section, percent: None })
+    }
+
+    fn event(&mut self, event: &BuildEvent) {
+        println!("{}", json_event(event));
+    }
+}
+
+/// A build event as a JSON object on a single line
+fn json_event(event: &BuildEvent) -> String {
+    match event {
+        BuildEvent::BuildStarted { layout, channels, params, tiles } => {
+            let channels: Vec<_> = channels.iter().map(|it| json_string(it)).collect();
+            format!(
+                "{{\"event\":\"build_started\",\"variation\":\"{}\",\"channels\":[{}],\"tiles\":{},\
+                 \"params\":{{\"nearest_neighbors\":{},\"random_sample_locations\":{},\
+                 \"backtrack_stages\":{},\"backtrack_percent\":{},\"cauchy_dispersion\":{},\
+                 \"guide_alpha\":{}}}}}",
+                layout,
+                channels.join(","),
+                tiles,
+                params.nearest_neighbors,
+                params.random_sample_locations,
+                params.backtrack_stages,
+                params.backtrack_percent,
+                params.cauchy_dispersion,
+                params.guide_alpha
+            )
+        }
+        BuildEvent::SampleStarted { id } => {
+            format!("{{\"event\":\"sample_started\",\"id\":{}}}", id)
+        }
+        BuildEvent::SampleFinished { id, elapsed } => format!(
+            "{{\"event\":\"sample_finished\",\"id\":{},\"elapsed\":{}}}",
+            id,
+            elapsed.as_secs_f64()
+        ),
+        BuildEvent::TileStarted { id, corners, seed } => format!(
+            "{{\"event\":\"tile_started\",\"id\":{},\"corners\":{},\"seed\":{}}}",
+            id,
+            json_corners(corners),
+            seed
+        ),
+        BuildEvent::TileFinished { id, corners, seed, elapsed } => format!(
+            "{{\"event\":\"tile_finished\",\"id\":{},\"corners\":{},\"seed\":{},\"elapsed\":{}}}",
+            id,
+            json_corners(corners),
+            seed,
+            elapsed.as_secs_f64()
+        ),
+        BuildEvent::CacheHit { key } => {
+            format!("{{\"event\":\"cache_hit\",\"key\":{}}}", json_string(key))
+        }
+        BuildEvent::CacheMiss { key } => {
+            format!("{{\"event\":\"cache_miss\",\"key\":{}}}", json_string(key))
+        }
+        BuildEvent::BuildFinished { tiles, retries, elapsed } => format!(
+            "{{\"event\":\"build_finished\",\"tiles\":{},\"retries\":{},\"elapsed\":{}}}",
+            tiles,
+            retries,
+            elapsed.as_secs_f64()
+        ),
+    }
+}
+
+struct JsonSubProgress {
+    section: ReportSection,
+    /// Last written percentage, to write a line only when it changes
+    percent: Option<usize>,
+}
+
+impl ts::GeneratorProgress for JsonSubProgress {
+    fn update(&mut self, update: ts::ProgressUpdate<'_>) {
+        let percent = update.total.current * 100 / update.total.total.max(1);
+        if self.percent == Some(percent) {
+            return;
+        }
+        self.percent = Some(percent);
+
+        println!(
+            "{{\"event\":\"progress\",\"section\":{},\"current\":{},\"total\":{},\"percent\":{}}}",
+            json_string(&self.section.name),
+            self.section.current,
+            self.section.total,
+            percent
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use omega_tile::{SynthesisParams, TileLayout, WTileVariation};
+    use std::time::Duration;
+
+    #[test]
+    fn escape_strings() {
+        assert_eq!(json_string("a\"b\\c"), r#""a\"b\\c""#);
+        assert_eq!(json_string("line\n\ttab"), r#""line\u000a\u0009tab""#);
+        assert_eq!(json_string("ω-tile"), "\"ω-tile\"");
+    }
+
+    #[test]
+    fn events_as_json_lines() {
+        let elapsed = Duration::from_millis(1500);
+        let cases = vec![
+            (
+                BuildEvent::BuildStarted {
+                    layout: TileLayout::Variation(WTileVariation::V4),
+                    channels: vec!["main".to_string(), "nor\"mal".to_string()],
+                    params: SynthesisParams::default(),
+                    tiles: 4,
+                },
+                r#"{"event":"build_started","variation":"v4","channels":["main","nor\"mal"],"tiles":4,"params":{"nearest_neighbors":50,"random_sample_locations":50,"backtrack_stages":5,"backtrack_percent":0.5,"cauchy_dispersion":1,"guide_alpha":0.8}}"#,
+            ),
+            (BuildEvent::SampleStarted { id: 2 }, r#"{"event":"sample_started","id":2}"#),
+            (
+                BuildEvent::SampleFinished { id: 2, elapsed },
+                r#"{"event":"sample_finished","id":2,"elapsed":1.5}"#,
+            ),
+            (
+                BuildEvent::TileStarted { id: 3, corners: [0, 1, 2, 3], seed: 0 },
+                r#"{"event":"tile_started","id":3,"corners":[0,1,2,3],"seed":0}"#,
+            ),
+            (
+                BuildEvent::TileFinished { id: 3, corners: [0, 1, 2, 3], seed: 1, elapsed },
+                r#"{"event":"tile_finished","id":3,"corners":[0,1,2,3],"seed":1,"elapsed":1.5}"#,
+            ),
+            (
+                BuildEvent::CacheHit { key: "a+b".to_string() },
+                r#"{"event":"cache_hit","key":"a+b"}"#,
+            ),
+            (
+                BuildEvent::CacheMiss { key: "a+b".to_string() },
+                r#"{"event":"cache_miss","key":"a+b"}"#,
+            ),
+            (
+                BuildEvent::BuildFinished { tiles: 4, retries: 1, elapsed },
+                r#"{"event":"build_finished","tiles":4,"retries":1,"elapsed":1.5}"#,
+            ),
+        ];
+
+        for (event, expected) in &cases {
+            let line = json_event(event);
+            assert_eq!(&line, expected);
+            assert!(!line.contains('\n'));
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use crate::error::Error;
+use crate::open_image;
+use std::fmt;
+use std::path::Path;
+use texture_synthesis as ts;
+use ts::image::{imageops::FilterType, DynamicImage, GenericImageView, GrayImage};
+
+/// Largest side of the image the analysis runs on
+const ANALYSIS_SIZE: u32 = 256;
+/// Autocorrelation below which pixels are considered unrelated
+const CORRELATION_THRESHOLD: f64 = 0.5;
+/// Rise of the autocorrelation, from its first minimum, for a peak to give the
+/// period of the texture rather than noise
+const PEAK_PROMINENCE: f64 = 0.2;
+/// Number of features a tile should hold along each axis
+const FEATURES_PER_TILE: u32 = 4;
+/// Quadrant brightness spread, relative to the standard deviation, above which
+/// the input is reported as not stationary
+const STATIONARITY_THRESHOLD: f64 = 0.5;
+
+/// Feature scale and stationarity of an input
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub dimensions: (u32, u32),
+    /// Period in pixels of the texture, at the first autocorrelation peak, or for
+    /// an aperiodic texture the distance over which the autocorrelation drops below 0.5
+    pub feature_size: u32,
+    /// Mean brightness of each quadrant, in the same order as the split samples
+    pub quadrant_means: [f64; 4],
+    /// Spread of the quadrant brightness relative to the standard deviation of the
+    /// whole input, 0 for a stationary texture
+    pub nonstationarity: f64,
+    /// Tile size holding a few features, which the input can provide in split mode
+    pub recommended_tile_size: u32,
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Dimensions: {}x{}", self.dimensions.0, self.dimensions.1)?;
+        writeln!(f, "Feature size: {} px", self.feature_size)?;
+        let q = &self.quadrant_means;
+        writeln!(f, "Quadrant brightness: {:.1} {:.1} {:.1} {:.1}", q[0], q[1], q[2], q[3])?;
+        writeln!(f, "Non-stationarity: {:.2}", self.nonstationarity)?;
+        writeln!(f, "Recommended tile size: {} px", self.recommended_tile_size)?;
+        for w in &self.warnings {
+            writeln!(f, "Warning: {}", w)?;
+        }
+        Ok(())
+    }
+}
+
+/// Analyze the image at `path`, see [`analyze_image`]
+pub fn analyze<Q>(path: Q) -> Result<Analysis, Error>
+where
+    Q: AsRef<Path>,
+{
+    analyze_image(&open_image(path)?)
+}
+
+/// Estimate the dominant feature size of an input from its autocorrelation, and
+/// check that its quadrants look alike, as ω-tiles need a stationary texture.
+pub fn analyze_image(img: &DynamicImage) -> Result<Analysis, Error> {
+    let (w, h) = img.dimensions();
+    if w < 2 || h < 2 {
+        return Err(Error::EmptyImage);
+    }
+
+    // Work on a small version, the feature size is scaled back afterwards
+    let small = if w.max(h) > ANALYSIS_SIZE {
+        let (sw, sh) = if w >= h {
+            (ANALYSIS_SIZE, (h * ANALYSIS_SIZE / w).max(2))
+        } else {
+            ((w * ANALYSIS_SIZE / h).max(2), ANALYSIS_SIZE)
+        };
+        img.resize_exact(sw, sh, FilterType::Triangle).to_luma()
+    } else {
+        img.to_luma()
+    };
+    let scale = w as f64 / small.width() as f64;
+
+    let (mean, var) = stats(&small, (0, 0, small.width(), small.height()));
+    let mut warnings = vec![];
+
+    let (sw, sh) = small.dimensions();
+    let max_lag = (sw.min(sh) / 2).max(1);
+    let lag = if var < 1e-6 {
+        warnings.push("The input is flat".to_string());
+        1
+    } else {
+        let correlations: Vec<f64> =
+            (0..=max_lag).map(|lag| autocorrelation(&small, mean, var, lag)).collect();
+        period(&correlations).unwrap_or_else(|| {
+            (1..=max_lag)
+                .find(|&lag| correlations[lag as usize] < CORRELATION_THRESHOLD)
+                .unwrap_or(max_lag)
+        })
+    };
+    let feature_size = ((lag as f64 * scale).round() as u32).max(1);
+
+    let (w2, h2) = (sw / 2, sh / 2);
+    let quadrants =
+        [(0, 0, w2, h2), (0, h2, w2, sh - h2), (w2, 0, sw - w2, h2), (w2, h2, sw - w2, sh - h2)];
+    let mut quadrant_means = [0.0; 4];
+    for (m, q) in quadrant_means.iter_mut().zip(&quadrants) {
+        *m = stats(&small, *q).0;
+    }
+    let spread = quadrant_means.iter().cloned().fold(f64::MIN, f64::max)
+        - quadrant_means.iter().cloned().fold(f64::MAX, f64::min);
+    let nonstationarity = if var < 1e-6 { 0.0 } else { spread / var.sqrt() };
+    if nonstationarity > STATIONARITY_THRESHOLD {
+        warnings.push(
+            "Quadrants differ a lot (e.g. a lighting gradient), the tiles will show blocks"
+                .to_string(),
+        );
+    }
+
+    // Split mode tiles are half of the input
+    let largest = (w.min(h) / 2) & !1;
+    let wanted = round_up(feature_size * FEATURES_PER_TILE, 16).max(32);
+    if wanted > largest {
+        warnings.push(format!(
+            "Features are large for this input, tiles of {} px would need a larger input",
+            wanted
+        ));
+    }
+    let recommended_tile_size = wanted.min(largest).max(2);
+
+    Ok(Analysis {
+        dimensions: (w, h),
+        feature_size,
+        quadrant_means,
+        nonstationarity,
+        recommended_tile_size,
+        warnings,
+    })
+}
+
+/// Lag of the first autocorrelation peak after its first minimum, `None` if the
+/// texture is not periodic
+fn period(correlations: &[f64]) -> Option<u32> {
+    let min = (1..correlations.len() - 1).find(|&i| correlations[i] <= correlations[i + 1])?;
+    let peak =
+        (min + 1..correlations.len() - 1).find(|&i| correlations[i] > correlations[i + 1])?;
+    if correlations[peak] - correlations[min] < PEAK_PROMINENCE {
+        return None;
+    }
+    Some(peak as u32)
+}
+
+fn round_up(n: u32, step: u32) -> u32 {
+    n.div_ceil(step) * step
+}
+
+/// Mean and variance of a region
+fn stats(img: &GrayImage, (x0, y0, w, h): (u32, u32, u32, u32)) -> (f64, f64) {
+    let n = (w * h).max(1) as f64;
+    let mut sum = 0.0;
+    let mut sum2 = 0.0;
+    for y in y0..y0 + h {
+        for x in x0..x0 + w {
+            let v = img.get_pixel(x, y)[0] as f64;
+            sum += v;
+            sum2 += v * v;
+        }
+    }
+    let mean = sum / n;
+    (mean, (sum2 / n - mean * mean).max(0.0))
+}
+
+/// Normalized autocorrelation at a horizontal and vertical offset of `lag`, averaged
+fn autocorrelation(img: &GrayImage, mean: f64, var: f64, lag: u32) -> f64 {
+    let (w, h) = img.dimensions();
+    let v = |x: u32, y: u32| img.get_pixel(x, y)[0] as f64 - mean;
+
+    let mut sum = 0.0;
+    let mut n = 0;
+    for y in 0..h {
+        for x in 0..w {
+            if x + lag < w {
+                sum += v(x, y) * v(x + lag, y);
+                n += 1;
+            }
+            if y + lag < h {
+                sum += v(x, y) * v(x, y + lag);
+                n += 1;
+            }
+        }
+    }
+
+    if n == 0 {
+        0.0
+    } else {
+        sum / (n as f64 * var)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use ts::image::Luma;
+
+    /// Waves of `period` pixels along both axes
+    fn waves((w, h): (u32, u32), period: f64) -> DynamicImage {
+        let wave = |t: u32| (2.0 * std::f64::consts::PI * t as f64 / period).sin();
+        DynamicImage::ImageLuma8(GrayImage::from_fn(w, h, |x, y| {
+            Luma([(128.0 + 60.0 * wave(x) + 60.0 * wave(y)).round() as u8])
+        }))
+    }
+
+    #[test]
+    fn feature_size_of_periodic_input() {
+        assert_eq!(analyze_image(&waves((128, 128), 16.0)).unwrap().feature_size, 16);
+        assert_eq!(analyze_image(&waves((160, 96), 24.0)).unwrap().feature_size, 24);
+    }
+
+    #[test]
+    fn feature_size_scaled_back() {
+        // Analyzed at half of its size
+        assert_eq!(analyze_image(&waves((512, 512), 40.0)).unwrap().feature_size, 40);
+    }
+
+    #[test]
+    fn feature_size_of_noise() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let img = DynamicImage::ImageLuma8(GrayImage::from_fn(128, 128, |_, _| Luma([rng.gen()])));
+        assert_eq!(analyze_image(&img).unwrap().feature_size, 1);
+    }
+}
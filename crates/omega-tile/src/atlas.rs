@@ -1,14 +1,17 @@
-use crate::{wtile, WTile};
+use crate::error::Error;
+use crate::{Direction, WTile};
 use crate::{DynamicImage, GenericImageView, Luma};
 use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 use std::collections::HashMap;
 use std::fmt;
+use texture_synthesis as ts;
+use ts::image::GrayImage;
 
 fn fit(x: i32, y: i32, w: &WTile, atlas: &HashMap<(i32, i32), (usize, WTile)>) -> bool {
     macro_rules! check {
         ($dir:ident, $dx:literal, $dy:literal) => {
             if let Some(other) = atlas.get(&(x + $dx, y + $dy)) {
-                if !w.is_connectable(wtile::Direction::$dir, &other.1) {
+                if !w.is_connectable(Direction::$dir, &other.1) {
                     return false;
                 }
             }
@@ -45,19 +48,20 @@ impl Atlas {
         self.data.get(&(x, y)).cloned()
     }
 
-    pub fn build_indices(&self) -> DynamicImage {
-        let mut res = DynamicImage::new_luma8(self.n, self.n);
-        let img = res.as_mut_luma8().unwrap();
+    /// Image of the tile index at each position
+    pub fn build_indices(&self) -> Result<DynamicImage, Error> {
+        let mut img = GrayImage::new(self.n, self.n);
 
         for y in 0..self.n {
             for x in 0..self.n {
-                let (id, _) = self.get(x as i32, y as i32).expect("Altas is not completed");
+                let (id, _) =
+                    self.get(x as i32, y as i32).ok_or(Error::AtlasUnsatisfiable((x, y)))?;
 
                 img.put_pixel(x, y, Luma([id as u8]));
             }
         }
 
-        res
+        Ok(DynamicImage::ImageLuma8(img))
     }
 }
 
@@ -65,12 +69,15 @@ impl fmt::Display for Atlas {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for y in 0..self.n {
             for x in 0..self.n {
-                let (id, _) = self.get(x as i32, y as i32).expect("Altas is not completed");
+                let id = match self.get(x as i32, y as i32) {
+                    Some((id, _)) => format!("{:02}", id),
+                    None => "--".to_string(),
+                };
 
                 if x == (self.n - 1) && y != (self.n - 1) {
-                    writeln!(f, "{:02}", id)?;
+                    writeln!(f, "{}", id)?;
                 } else {
-                    write!(f, "{:02} ", id)?;
+                    write!(f, "{} ", id)?;
                 }
             }
         }
@@ -79,10 +86,16 @@ impl fmt::Display for Atlas {
     }
 }
 
-pub fn build_atlas(tiles: &Vec<WTile>, n: u32, seed: u64) -> Atlas {
+/// Lay out `n` x `n` tiles so that every edge matches its neighbor
+pub fn build_atlas(tiles: &[WTile], n: u32, seed: u64) -> Result<Atlas, Error> {
+    let tile_dimensions = match tiles.first() {
+        Some(it) => it.img.dimensions(),
+        None => return Err(Error::InvalidInput("The atlas needs at least one tile".to_string())),
+    };
+
     let mut atlas: HashMap<(i32, i32), (usize, WTile)> = HashMap::new();
     let mut rng = StdRng::seed_from_u64(seed);
-    let id_tiles: Vec<(usize, WTile)> = tiles.into_iter().cloned().enumerate().collect();
+    let id_tiles: Vec<(usize, WTile)> = tiles.iter().cloned().enumerate().collect();
 
     let mut shuffle = || {
         let mut res: Vec<(usize, WTile)> = id_tiles.clone();
@@ -115,9 +128,9 @@ pub fn build_atlas(tiles: &Vec<WTile>, n: u32, seed: u64) -> Atlas {
         }
 
         if !success {
-            panic!("Generate combined image fail!");
+            return Err(Error::AtlasUnsatisfiable((x, y)));
         }
     }
 
-    Atlas { data: atlas, n, tile_dimensions: tiles[0].img.dimensions() }
+    Ok(Atlas { data: atlas, n, tile_dimensions })
 }
@@ -0,0 +1,693 @@
+use crate::cache::{self, TileCache};
+use crate::{deep, wtile};
+use crate::{
+    open_image, BuildEvent, BuildResult, CancelToken, ChannelTiles, Channels, ColorOutput,
+    DeepTiles, Error, ExamplePool, FinishedTile, Mask, Preprocess, Report, ReportSection,
+    RetryPolicy, SampleMode, SynthesisParams, TileLayout, WTileContext, WTileVariation,
+};
+use std::path::Path;
+use std::time::Instant;
+use texture_synthesis as ts;
+use ts::image::{DynamicImage, GenericImage, GenericImageView, Pixel, Rgba};
+
+/// Where the samples, one for each corner color, come from
+#[derive(Debug, Clone)]
+pub enum SampleSource {
+    /// Samples made from the input channels, see [`SampleMode`]
+    Input(Channels),
+    /// Flat colored samples, to check the layout of the tiles
+    Test,
+}
+
+/// Report which ignores the progress
+struct NoReport;
+
+struct NoProgress;
+
+impl ts::GeneratorProgress for NoProgress {
+    fn update(&mut self, _info: ts::ProgressUpdate<'_>) {}
+}
+
+impl Report for NoReport {
+    fn sub_progress_bar(&mut self, _section: ReportSection) -> Box<dyn ts::GeneratorProgress> {
+        Box::new(NoProgress)
+    }
+}
+
+/// Callback given each finished tile
+type OnTile = Box<dyn FnMut(&FinishedTile)>;
+
+/// Report which also hands the finished tiles to a callback
+struct TileCallback {
+    inner: Box<dyn Report>,
+    on_tile: OnTile,
+}
+
+impl Report for TileCallback {
+    fn sub_progress_bar(&mut self, section: ReportSection) -> Box<dyn ts::GeneratorProgress> {
+        self.inner.sub_progress_bar(section)
+    }
+
+    fn event(&mut self, event: &BuildEvent) {
+        self.inner.event(event)
+    }
+
+    fn tile_finished(&mut self, tile: &FinishedTile) {
+        self.inner.tile_finished(tile);
+        (self.on_tile)(tile)
+    }
+}
+
+/// Settings of a build, made by [`OmegaTile::builder`]
+pub struct OmegaTileBuilder {
+    source: Option<SampleSource>,
+    mode: SampleMode,
+    preprocess: Preprocess,
+    color: ColorOutput,
+    layout: TileLayout,
+    mask: Mask,
+    params: SynthesisParams,
+    pool: ExamplePool,
+    retry: Option<RetryPolicy>,
+    report: Box<dyn Report>,
+    on_tile: Option<OnTile>,
+    cache: Option<Box<dyn TileCache>>,
+    cancel: CancelToken,
+    seed: u64,
+}
+
+impl Default for OmegaTileBuilder {
+    fn default() -> Self {
+        OmegaTileBuilder {
+            source: None,
+            mode: SampleMode::Split,
+            preprocess: Preprocess::default(),
+            color: ColorOutput::default(),
+            layout: TileLayout::Variation(WTileVariation::V16),
+            mask: Mask::default(),
+            params: SynthesisParams::default(),
+            pool: ExamplePool::default(),
+            retry: None,
+            report: Box::new(NoReport),
+            on_tile: None,
+            cache: None,
+            cancel: CancelToken::default(),
+            seed: 0,
+        }
+    }
+}
+
+impl OmegaTileBuilder {
+    pub fn new() -> OmegaTileBuilder {
+        OmegaTileBuilder::default()
+    }
+
+    /// Build a ω-tile set for each channel of the input.
+    ///
+    /// Inputs may be 16 bits per channel PNGs: texture-synthesis then works on
+    /// their high bytes and the low bytes follow the same patch placement, so the
+    /// tiles keep the 16 bits, see [`ChannelTiles::deep`].
+    pub fn input(mut self, channels: Channels) -> Self {
+        self.source = Some(SampleSource::Input(channels));
+        self
+    }
+
+    /// Build the tiles from flat colored samples
+    pub fn test_samples(mut self) -> Self {
+        self.source = Some(SampleSource::Test);
+        self
+    }
+
+    pub fn source(mut self, source: SampleSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// How the samples are made from the input, split by default
+    pub fn mode(mut self, mode: SampleMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn preprocess(mut self, preprocess: Preprocess) -> Self {
+        self.preprocess = preprocess;
+        self
+    }
+
+    pub fn color(mut self, color: ColorOutput) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Tiles of the set, 16 tiles by default
+    pub fn variation(mut self, variation: WTileVariation) -> Self {
+        self.layout = TileLayout::Variation(variation);
+        self
+    }
+
+    /// Build only the tiles with these corner colors (a, b, c, d)
+    pub fn corners(mut self, corners: Vec<[usize; 4]>) -> Self {
+        self.layout = TileLayout::Custom(corners);
+        self
+    }
+
+    pub fn mask(mut self, mask: Mask) -> Self {
+        self.mask = mask;
+        self
+    }
+
+    pub fn params(mut self, params: SynthesisParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Examples the tiles are synthesized from, besides the samples
+    pub fn example_pool(mut self, pool: ExamplePool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Synthesize again the tiles with a poor quality score
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    pub fn report(mut self, report: impl Report + 'static) -> Self {
+        self.report = Box::new(report);
+        self
+    }
+
+    /// Call `on_tile` with each tile as soon as it is built, so it can be shown or
+    /// saved before the whole set is done. A tile replaced by a retry is given again,
+    /// with `replaced` set.
+    pub fn on_tile(mut self, on_tile: impl FnMut(&FinishedTile) + 'static) -> Self {
+        self.on_tile = Some(Box::new(on_tile));
+        self
+    }
+
+    pub fn cache(mut self, cache: impl TileCache + 'static) -> Self {
+        self.cache = Some(Box::new(cache));
+        self
+    }
+
+    /// Token to stop the build from another thread
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Seed of the synthesis, builds with the same seed make the same tiles
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Check the settings
+    pub fn build(self) -> Result<OmegaTile, Error> {
+        let source = self.source.ok_or_else(|| {
+            Error::InvalidInput("No sample source, set an input or the test samples".to_string())
+        })?;
+
+        wtile::check_corners(&self.layout.corners())?;
+        self.params.check()?;
+
+        if let SampleSource::Input(channels) = &source {
+            if channels.len() > 1 && !self.pool.references.is_empty() {
+                return Err(Error::InvalidInput(
+                    "Reference images are not supported with multiple channels".to_string(),
+                ));
+            }
+        }
+
+        let report: Box<dyn Report> = match self.on_tile {
+            Some(on_tile) => Box::new(TileCallback { inner: self.report, on_tile }),
+            None => self.report,
+        };
+
+        Ok(OmegaTile {
+            source,
+            mode: self.mode,
+            preprocess: self.preprocess,
+            color: self.color,
+            layout: self.layout,
+            mask: self.mask,
+            params: self.params,
+            pool: self.pool,
+            retry: self.retry,
+            report,
+            cache: self.cache,
+            cancel: self.cancel,
+            seed: self.seed,
+        })
+    }
+}
+
+/// A ω-tile set build.
+///
+/// ```no_run
+/// use omega_tile::{Channels, OmegaTile, WTileVariation};
+///
+/// let result = OmegaTile::builder()
+///     .input(Channels::from("imgs/grass.png"))
+///     .variation(WTileVariation::V4)
+///     .build()?
+///     .run()?;
+/// # Ok::<(), omega_tile::Error>(())
+/// ```
+pub struct OmegaTile {
+    source: SampleSource,
+    mode: SampleMode,
+    preprocess: Preprocess,
+    color: ColorOutput,
+    layout: TileLayout,
+    mask: Mask,
+    params: SynthesisParams,
+    pool: ExamplePool,
+    retry: Option<RetryPolicy>,
+    report: Box<dyn Report>,
+    cache: Option<Box<dyn TileCache>>,
+    cancel: CancelToken,
+    seed: u64,
+}
+
+impl OmegaTile {
+    pub fn builder() -> OmegaTileBuilder {
+        OmegaTileBuilder::default()
+    }
+
+    /// Build the tiles, with one ω-tile set for each channel of the input, or a
+    /// single `test` channel with the test samples
+    pub fn run(self) -> Result<BuildResult, Error> {
+        let OmegaTile { source, mut cache, .. } = self;
+
+        let (channels, inputs, low, resized) = match &source {
+            SampleSource::Input(channels) => {
+                let (inputs, low, resized) = open_inputs(channels, &self.preprocess, self.mode)?;
+                (channels.clone(), inputs, low, resized)
+            }
+            SampleSource::Test => (Channels::new("test", ""), vec![], vec![], false),
+        };
+
+        if low.iter().any(Option::is_some) {
+            if self.mask.feather() > 0 {
+                return Err(Error::InvalidInput(
+                    "Feathered masks are not supported with 16 bits inputs".to_string(),
+                ));
+            }
+            if !self.pool.references.is_empty() {
+                return Err(Error::InvalidInput(
+                    "Reference images are not supported with 16 bits inputs".to_string(),
+                ));
+            }
+        }
+
+        let input_keys: Vec<_> = inputs.iter().map(cache::hash_image).collect();
+        if let (Some(cache), Some(key)) = (cache.as_mut(), input_keys.first()) {
+            cache.set_input(&channels.primary().path, key);
+        }
+
+        let mut ctx = WTileContext {
+            pb: self.report,
+            cache,
+            params: self.params.clone(),
+            preprocess: self.preprocess.clone(),
+            color: self.color,
+            pool: self.pool.clone(),
+            examples: vec![vec![]],
+            cancel: self.cancel,
+            input_keys,
+            seed: self.seed,
+            resized,
+            channels: channels.len(),
+        };
+
+        let start = Instant::now();
+        ctx.emit(BuildEvent::BuildStarted {
+            layout: self.layout.clone(),
+            channels: channels.iter().map(|it| it.name.clone()).collect(),
+            params: self.params.clone(),
+            tiles: self.layout.tile_count(),
+        });
+
+        let (channels, retries) = match source {
+            SampleSource::Input(input) => {
+                let samples = ctx.build_samples(self.mode, inputs)?;
+
+                let opts = ctx.tile_options(
+                    &self.layout,
+                    self.mode,
+                    &samples,
+                    &self.mask,
+                    &self.pool.key(),
+                )?;
+                let mut tiles = ctx.build_n_w_tiles(&self.layout, &samples, &opts)?;
+
+                let retries = match &self.retry {
+                    Some(policy) => ctx.retry_tiles(policy, &mut tiles, &samples, &opts)?,
+                    None => vec![],
+                };
+
+                let color = self.color;
+                let channels = input
+                    .iter()
+                    .zip(&low)
+                    .enumerate()
+                    .map(|(i, (ch, low))| ChannelTiles {
+                        name: ch.name.clone(),
+                        tiles: tiles[i].clone(),
+                        samples: samples[i].iter().map(|it| color.convert(it)).collect(),
+                        deep: low.map(|l| DeepTiles {
+                            tiles: tiles[i]
+                                .iter()
+                                .zip(&tiles[l])
+                                .map(|(high, low)| deep::join_bytes(&high.img, &low.img, color))
+                                .collect(),
+                            samples: samples[i]
+                                .iter()
+                                .zip(&samples[l])
+                                .map(|(high, low)| deep::join_bytes(high, low, color))
+                                .collect(),
+                        }),
+                    })
+                    .collect();
+                (channels, retries)
+            }
+            SampleSource::Test => {
+                let samples = test_samples();
+                let tiles = ctx.build_test_tiles(&self.layout, &samples)?;
+                (
+                    vec![ChannelTiles { name: "test".to_string(), tiles, samples, deep: None }],
+                    vec![],
+                )
+            }
+        };
+
+        ctx.emit(BuildEvent::BuildFinished {
+            tiles: self.layout.tile_count(),
+            retries: retries.len(),
+            elapsed: start.elapsed(),
+        });
+
+        Ok(BuildResult { channels, retries })
+    }
+}
+
+/// Inputs, low bytes index of each channel and whether they are resized
+type OpenedInputs = (Vec<DynamicImage>, Vec<Option<usize>>, bool);
+
+/// Open the input of each channel.
+///
+/// 16 bits inputs are cropped and resized first, as resampling their bytes apart
+/// would mix them up. They are then split into their high bytes, in place of the
+/// input, and their low bytes, added after every channel. Gives the index of the
+/// low bytes of each channel, and whether the inputs are resized.
+fn open_inputs(
+    channels: &Channels,
+    preprocess: &Preprocess,
+    mode: SampleMode,
+) -> Result<OpenedInputs, Error> {
+    let deep = channels
+        .iter()
+        .map(|it| deep::open_deep_image(Path::new(&it.path)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if deep.iter().all(Option::is_none) {
+        let inputs =
+            channels.iter().map(|it| open_image(&it.path)).collect::<Result<Vec<_>, _>>()?;
+        return Ok((inputs, vec![None; channels.len()], false));
+    }
+
+    let tiles_per_side = mode.tiles_per_side();
+    let (mut inputs, mut lows, mut low_index) = (vec![], vec![], vec![]);
+    for (it, deep) in channels.iter().zip(deep) {
+        match deep {
+            Some(img) => {
+                let (high, low) = deep::split_bytes(&preprocess.resize(&img, tiles_per_side)?);
+                inputs.push(high);
+                low_index.push(Some(channels.len() + lows.len()));
+                lows.push(low);
+            }
+            None => {
+                let img = preprocess.resize(&open_image(&it.path)?, tiles_per_side)?;
+                inputs.push(DynamicImage::ImageRgba8(img));
+                low_index.push(None);
+            }
+        }
+    }
+    inputs.extend(lows);
+    Ok((inputs, low_index, true))
+}
+
+/// Red, green, blue and gray samples
+fn test_samples() -> Vec<DynamicImage> {
+    fn fill(img: &mut DynamicImage, color: Rgba<u8>) {
+        let dim = img.dimensions();
+        for y in 0..dim.1 {
+            for x in 0..dim.0 {
+                img.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    let colors = [
+        Rgba::from_channels(255, 0, 0, 255),
+        Rgba::from_channels(0, 255, 0, 255),
+        Rgba::from_channels(0, 0, 255, 255),
+        Rgba::from_channels(128, 128, 128, 255),
+    ];
+
+    colors
+        .iter()
+        .map(|&color| {
+            let mut img = DynamicImage::new_rgb8(128, 128);
+            fill(&mut img, color);
+            img
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{deep::Rgba16Image, MemoryCache, Quality};
+    use ts::image::Rgba;
+
+    #[test]
+    fn deep_input_keeps_low_bytes() {
+        let (w, h) = (32, 32);
+        let img = Rgba16Image::from_fn(w, h, |x, y| {
+            let v = (x * 2003 + y * 1009) as u16;
+            Rgba([v, v.wrapping_mul(3), v.wrapping_mul(7), u16::MAX])
+        });
+        let path =
+            std::env::temp_dir().join(format!("omega-tile-build-{}.png", std::process::id()));
+        deep::save_deep_image(&img, ColorOutput::Rgb, &path).unwrap();
+
+        let result = OmegaTile::builder()
+            .input(Channels::new("main", &path.to_string_lossy()))
+            .variation(WTileVariation::V4)
+            .params(SynthesisParams::preset(Quality::Fast))
+            .build()
+            .unwrap()
+            .run();
+        std::fs::remove_file(&path).unwrap();
+
+        let channel = result.unwrap().channels.remove(0);
+        let deep = channel.deep.expect("16 bits tiles");
+        assert_eq!(deep.tiles.len(), 4);
+        assert_eq!(deep.samples.len(), 4);
+
+        // The corners of a tile are kept from the samples, with their 16 bits
+        for (tile, t) in deep.tiles.iter().zip(&channel.tiles) {
+            let [a, _, _, d] = t.corners();
+            assert_eq!(tile.dimensions(), (16, 16));
+            assert_eq!(tile.get_pixel(0, 0), deep.samples[a].get_pixel(8, 8));
+            assert_eq!(tile.get_pixel(15, 15), deep.samples[d].get_pixel(7, 7));
+        }
+
+        // The 16 bits tiles are saved without losing their low bytes
+        deep::save_deep_image(&deep.tiles[0], ColorOutput::Rgb, &path).unwrap();
+        let saved = deep::open_deep_image(&path);
+        std::fs::remove_file(&path).unwrap();
+        let saved = saved.unwrap().expect("16 bits PNG");
+        assert_eq!(saved.dimensions(), deep.tiles[0].dimensions());
+        assert!(saved.pixels().eq(deep.tiles[0].pixels()));
+    }
+
+    #[test]
+    fn deep_tiles_from_cache() {
+        let img = Rgba16Image::from_fn(32, 32, |x, y| {
+            let v = (x * 2003 + y * 1009) as u16;
+            Rgba([v, v.wrapping_mul(3), v.wrapping_mul(7), u16::MAX])
+        });
+        let path =
+            std::env::temp_dir().join(format!("omega-tile-deep-cache-{}.png", std::process::id()));
+        deep::save_deep_image(&img, ColorOutput::Rgb, &path).unwrap();
+
+        let cache = MemoryCache::new();
+        let build = || {
+            OmegaTile::builder()
+                .input(Channels::new("main", &path.to_string_lossy()))
+                .variation(WTileVariation::V4)
+                .params(SynthesisParams::preset(Quality::Fast))
+                .cache(cache.clone())
+                .build()
+                .unwrap()
+                .run()
+                .unwrap()
+        };
+        let first = build();
+        let cached = cache.len();
+        let second = build();
+        std::fs::remove_file(&path).unwrap();
+
+        // The low bytes of the tiles are cached along the high ones
+        assert_eq!(cache.len(), cached);
+        let (a, b) = (&first.channels[0], &second.channels[0]);
+        let (a, b) = (a.deep.as_ref().unwrap(), b.deep.as_ref().unwrap());
+        for (a, b) in a.tiles.iter().zip(&b.tiles) {
+            assert!(a.pixels().eq(b.pixels()));
+        }
+    }
+
+    #[test]
+    fn channels_follow_the_primary_synthesis() {
+        let main = ts::image::RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([(x * 37 % 256) as u8, (y * 53 % 256) as u8, ((x ^ y) * 7 % 256) as u8, 255])
+        });
+        let mut copy = main.clone();
+        ts::image::imageops::invert(&mut copy);
+        let dir = std::env::temp_dir();
+        let main_path = dir.join(format!("omega-tile-main-{}.png", std::process::id()));
+        let copy_path = dir.join(format!("omega-tile-copy-{}.png", std::process::id()));
+        main.save(&main_path).unwrap();
+        copy.save(&copy_path).unwrap();
+
+        let result = OmegaTile::builder()
+            .input(
+                Channels::new("main", &main_path.to_string_lossy())
+                    .with_channel("copy", &copy_path.to_string_lossy()),
+            )
+            .variation(WTileVariation::V4)
+            .params(SynthesisParams::preset(Quality::Fast))
+            .build()
+            .unwrap()
+            .run();
+        std::fs::remove_file(&main_path).unwrap();
+        std::fs::remove_file(&copy_path).unwrap();
+
+        // Every pixel of the second channel is copied from where the primary one was
+        let channels = result.unwrap().channels;
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].tiles.len(), channels[1].tiles.len());
+        for (a, b) in channels[0].tiles.iter().zip(&channels[1].tiles) {
+            let (a, b) = (a.img.to_rgb(), b.img.to_rgb());
+            assert_eq!(a.dimensions(), b.dimensions());
+            for (p, q) in a.pixels().zip(b.pixels()) {
+                assert_eq!(q.0, [255 - p[0], 255 - p[1], 255 - p[2]]);
+            }
+        }
+    }
+
+    /// Report which cancels the build at the first progress of a synthesis
+    struct CancelOnProgress(CancelToken);
+
+    impl Report for CancelOnProgress {
+        fn sub_progress_bar(&mut self, _section: ReportSection) -> Box<dyn ts::GeneratorProgress> {
+            let token = self.0.clone();
+            Box::new(move |_: ts::ProgressUpdate<'_>| token.cancel())
+        }
+    }
+
+    #[test]
+    fn cancel_stops_running_synthesis() {
+        let img = ts::image::RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([(x * 37 % 256) as u8, (y * 53 % 256) as u8, ((x ^ y) * 7 % 256) as u8, 255])
+        });
+        let path =
+            std::env::temp_dir().join(format!("omega-tile-cancel-{}.png", std::process::id()));
+        img.save(&path).unwrap();
+
+        let token = CancelToken::new();
+        let result = OmegaTile::builder()
+            .input(Channels::new("main", &path.to_string_lossy()))
+            .variation(WTileVariation::V4)
+            .params(SynthesisParams::preset(Quality::Fast))
+            .report(CancelOnProgress(token.clone()))
+            .cancel_token(token)
+            .build()
+            .unwrap()
+            .run();
+        std::fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(Error::Cancelled) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("the build was not cancelled"),
+        }
+    }
+
+    #[test]
+    fn on_tile_delivers_each_tile() {
+        let img = ts::image::RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([(x * 37 % 256) as u8, (y * 53 % 256) as u8, ((x ^ y) * 7 % 256) as u8, 255])
+        });
+        let path =
+            std::env::temp_dir().join(format!("omega-tile-on-tile-{}.png", std::process::id()));
+        img.save(&path).unwrap();
+
+        let delivered = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let sink = delivered.clone();
+        let result = OmegaTile::builder()
+            .input(Channels::new("main", &path.to_string_lossy()))
+            .variation(WTileVariation::V4)
+            .params(SynthesisParams::preset(Quality::Fast))
+            // No score is below the threshold, so every tile is retried once
+            .retry(RetryPolicy { threshold: -1.0, attempts: 1 })
+            .on_tile(move |tile| sink.borrow_mut().push(tile.clone()))
+            .build()
+            .unwrap()
+            .run();
+        std::fs::remove_file(&path).unwrap();
+
+        let result = result.unwrap();
+        let tiles = &result.channels[0].tiles;
+        let delivered = delivered.borrow();
+        let (first, replaced): (Vec<&FinishedTile>, Vec<&FinishedTile>) =
+            delivered.iter().partition(|it| !it.replaced);
+
+        // Each tile is delivered once when built, then once more for its retry
+        let mut ids: Vec<_> = first.iter().map(|it| it.id).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..tiles.len()).collect::<Vec<_>>());
+        let mut ids: Vec<_> = replaced.iter().map(|it| it.id).collect();
+        ids.sort_unstable();
+        let mut retried: Vec<_> = result.retries.iter().map(|it| it.id).collect();
+        retried.sort_unstable();
+        assert_eq!(ids, retried);
+        assert_eq!(retried.len(), tiles.len());
+
+        // The last delivery of a tile is the returned one
+        for (id, tile) in tiles.iter().enumerate() {
+            let last = delivered.iter().rev().find(|it| it.id == id).unwrap();
+            assert!(last.replaced);
+            assert_eq!(last.corners, tile.corners());
+            assert_eq!(last.tiles.len(), 1);
+            assert_eq!(last.tiles[0].img.raw_pixels(), tile.img.raw_pixels());
+        }
+    }
+
+    #[test]
+    fn refuse_non_finite_params() {
+        let params = SynthesisParams { backtrack_percent: f32::NAN, ..SynthesisParams::default() };
+        let result = OmegaTile::builder().test_samples().params(params).build();
+        match result {
+            Err(Error::InvalidInput(_)) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("a NaN backtrack percent was accepted"),
+        }
+
+        let params = SynthesisParams { guide_alpha: f32::INFINITY, ..SynthesisParams::default() };
+        assert!(OmegaTile::builder().test_samples().params(params).build().is_err());
+    }
+}
@@ -0,0 +1,614 @@
+use super::{key_hash, TileCache, ALGORITHM_VERSION, KEY_VERSION};
+use crate::error::Error;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use texture_synthesis as ts;
+use ts::image::{DynamicImage, ImageOutputFormat};
+
+/// Environment variable overriding the default cache directory
+const CACHE_DIR_ENV: &str = "OMEGA_TILE_CACHE_DIR";
+
+/// Hit and miss counts of the last build, kept in the cache directory
+const STATS_FILE: &str = "stats.txt";
+
+/// Per-user cache directory of the platform
+fn default_dir() -> PathBuf {
+    let var = |name| env::var_os(name).filter(|it| !it.is_empty()).map(PathBuf::from);
+
+    let base = if cfg!(target_os = "windows") {
+        var("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|it| it.join("Library").join("Caches"))
+    } else {
+        var("XDG_CACHE_HOME").or_else(|| var("HOME").map(|it| it.join(".cache")))
+    };
+
+    base.unwrap_or_else(env::temp_dir).join("omega-tile")
+}
+
+/// Write `data` to a temporary file next to `path`, then rename it, so other
+/// processes never see a partially written file
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+    let name = path.file_name().map_or("".into(), |it| it.to_string_lossy());
+    let tmp = path.with_file_name(format!(".{}.{}.{}.tmp", name, process::id(), n));
+
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+fn checksum(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hex::encode(hasher.result())
+}
+
+fn to_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |it| it.as_secs())
+}
+
+fn from_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Manifest of an entry, kept in a text file next to its image
+#[derive(Default)]
+struct Meta {
+    /// Path of the primary input
+    source: Option<String>,
+    /// Hash of the primary input
+    input: Option<String>,
+    key: Option<String>,
+    /// Version of omega-tile and of its algorithms which made the image
+    version: Option<String>,
+    algorithm: Option<u32>,
+    created: Option<u64>,
+    /// SHA-256 of the png file
+    checksum: Option<String>,
+}
+
+impl Meta {
+    fn read(path: &Path) -> Meta {
+        let mut meta = Meta::default();
+        if let Ok(text) = fs::read_to_string(path) {
+            for line in text.lines() {
+                let mut parts = line.splitn(2, '=');
+                let (name, value) = match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) if !value.is_empty() => (name, value),
+                    _ => continue,
+                };
+                match name {
+                    "source" => meta.source = Some(value.to_owned()),
+                    "input" => meta.input = Some(value.to_owned()),
+                    "key" => meta.key = Some(value.to_owned()),
+                    "version" => meta.version = Some(value.to_owned()),
+                    "algorithm" => meta.algorithm = value.parse().ok(),
+                    "created" => meta.created = value.parse().ok(),
+                    "sha256" => meta.checksum = Some(value.to_owned()),
+                    _ => (),
+                }
+            }
+        }
+        meta
+    }
+
+    /// Write the manifest, returns its size
+    fn write(&self, path: &Path) -> Result<u64, Error> {
+        let mut text = String::new();
+        let mut line = |name: &str, value: Option<String>| {
+            text.push_str(&format!("{}={}\n", name, value.unwrap_or_default()));
+        };
+        line("source", self.source.clone());
+        line("input", self.input.clone());
+        line("key", self.key.clone());
+        line("version", self.version.clone());
+        line("algorithm", self.algorithm.map(|it| it.to_string()));
+        line("created", self.created.map(|it| it.to_string()));
+        line("sha256", self.checksum.clone());
+
+        write_atomic(path, text.as_bytes()).map_err(Error::in_cache(path))?;
+        Ok(text.len() as u64)
+    }
+
+    /// Whether the image was made by this version of omega-tile and its algorithms
+    fn is_compatible(&self) -> bool {
+        self.version.as_deref() == Some(KEY_VERSION) && self.algorithm == Some(ALGORITHM_VERSION)
+    }
+}
+
+/// A cached image
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+    /// Size of the image and its metadata
+    pub bytes: u64,
+    /// Last time the entry was written or read, the modification time of its image
+    pub last_used: SystemTime,
+    pub created: Option<SystemTime>,
+    /// Input the entry was built from
+    pub source: Option<String>,
+    /// Hash of the input the entry was built from
+    pub input: Option<String>,
+    /// Cache key, made of the parameters of the entry
+    pub key: Option<String>,
+    /// omega-tile version which made the entry
+    pub version: Option<String>,
+    /// False when made by another version of omega-tile or of its algorithms,
+    /// such entries are never read and are removed by the next build
+    pub compatible: bool,
+}
+
+/// Summary of the cache content
+#[derive(Debug, Clone)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+    /// Hits and misses of the last build
+    pub last_run: Option<(u64, u64)>,
+}
+
+impl CacheStats {
+    /// Fraction of the lookups of the last build found in the cache
+    pub fn hit_rate(&self) -> Option<f64> {
+        match self.last_run {
+            Some((hits, misses)) if hits + misses > 0 => Some(hits as f64 / (hits + misses) as f64),
+            _ => None,
+        }
+    }
+}
+
+/// Images synthesized by previous builds, stored as png files in a directory.
+///
+/// Each image has a manifest file next to it, recording how and when it was made.
+/// The modification time of the image is its last use, updated when it is read,
+/// so reads never rewrite the manifest. Entries made by another version of
+/// omega-tile or of its algorithms are removed.
+///
+/// Files are written under a temporary name then renamed, and images are checked
+/// against the checksum of their metadata when read, so several processes can
+/// share a cache directory.
+pub struct Cache {
+    dir: PathBuf,
+    /// Size budget in bytes, least recently used entries are evicted above it
+    max_size: Option<u64>,
+    /// Input of the current build, recorded in the metadata of the entries
+    source: Option<String>,
+    /// Hash of the primary input of the current build
+    input: Option<String>,
+    /// Whether incompatible entries were removed
+    swept: bool,
+    /// Size of the entries, scanned at the first write with a size budget then
+    /// kept up to date, so the directory is only scanned again to evict entries
+    size: Option<u64>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Cache {
+    /// Cache in `OMEGA_TILE_CACHE_DIR` if set, or else in the per-user cache directory
+    pub fn new() -> Cache {
+        match env::var_os(CACHE_DIR_ENV).filter(|it| !it.is_empty()) {
+            Some(dir) => Cache::with_dir(dir),
+            None => Cache::with_dir(default_dir()),
+        }
+    }
+
+    /// Cache in `dir`, which is created when the first image is written
+    pub fn with_dir<P: Into<PathBuf>>(dir: P) -> Cache {
+        Cache {
+            dir: dir.into(),
+            max_size: None,
+            source: None,
+            input: None,
+            swept: false,
+            size: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Evict the least recently used entries when the cache grows above `bytes`
+    pub fn with_max_size(mut self, bytes: u64) -> Cache {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.png", key_hash(key)))
+    }
+
+    /// Image at `path` and its manifest, if it matches the checksum of the manifest
+    /// and was made by this version
+    fn read_checked(path: &Path) -> Option<(DynamicImage, Meta)> {
+        let meta = Meta::read(&path.with_extension("txt"));
+        if !meta.is_compatible() {
+            return None;
+        }
+        let data = fs::read(path).ok()?;
+        if Some(checksum(&data)) != meta.checksum {
+            return None;
+        }
+        let img = ts::image::load_from_memory(&data).ok()?;
+        Some((img, meta))
+    }
+
+    /// Every image in the cache, the least recently used first
+    pub fn entries(&self) -> Result<Vec<CacheEntry>, Error> {
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(it) => it,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(Error::Cache((self.dir.clone(), e))),
+        };
+
+        let mut res = vec![];
+        for entry in dir {
+            let path = entry.map_err(Error::in_cache(&self.dir))?.path();
+            if path.extension().is_none_or(|it| it != "png") {
+                continue;
+            }
+
+            let meta_path = path.with_extension("txt");
+            let meta = Meta::read(&meta_path);
+            let compatible = meta.is_compatible();
+
+            let img_meta = match fs::metadata(&path) {
+                Ok(it) => it,
+                // Removed by another process
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::Cache((path, e))),
+            };
+            let meta_bytes = fs::metadata(&meta_path).map_or(0, |it| it.len());
+            let modified = img_meta.modified().map_err(Error::in_cache(&path))?;
+
+            res.push(CacheEntry {
+                path,
+                bytes: img_meta.len() + meta_bytes,
+                last_used: modified,
+                created: meta.created.map(from_secs),
+                source: meta.source,
+                input: meta.input,
+                key: meta.key,
+                version: meta.version,
+                compatible,
+            });
+        }
+
+        res.sort_by_key(|it| it.last_used);
+        Ok(res)
+    }
+
+    pub fn stats(&self) -> Result<CacheStats, Error> {
+        let entries = self.entries()?;
+
+        let last_run = fs::read_to_string(self.dir.join(STATS_FILE)).ok().and_then(|text| {
+            let mut values = text.split_whitespace().map(|it| it.parse::<u64>().ok());
+            Some((values.next()??, values.next()??))
+        });
+
+        Ok(CacheStats {
+            entries: entries.len(),
+            bytes: entries.iter().map(|it| it.bytes).sum(),
+            last_run,
+        })
+    }
+
+    fn remove(entry: &CacheEntry) -> Result<(), Error> {
+        // Another process may remove the same entry
+        for path in &[entry.path.clone(), entry.path.with_extension("txt")] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(Error::Cache((path.clone(), e)))
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the entries made by another version of omega-tile or of its
+    /// algorithms, returns the number of removed entries
+    pub fn remove_incompatible(&self) -> Result<usize, Error> {
+        let mut n = 0;
+        for entry in self.entries()? {
+            if !entry.compatible {
+                Cache::remove(&entry)?;
+                n += 1;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Remove the entries not used for `age`, returns the number of removed entries
+    pub fn prune_older_than(&self, age: Duration) -> Result<usize, Error> {
+        let now = SystemTime::now();
+        let mut n = 0;
+        for entry in self.entries()? {
+            // Entries from the future are kept
+            if now.duration_since(entry.last_used).is_ok_and(|it| it > age) {
+                Cache::remove(&entry)?;
+                n += 1;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Remove the least recently used entries until the cache fits in `bytes`,
+    /// returns the number of removed entries
+    pub fn evict_to(&self, bytes: u64) -> Result<usize, Error> {
+        Ok(self.evict(bytes)?.0)
+    }
+
+    /// Evict down to `bytes`, returns the number of removed entries and the size left
+    fn evict(&self, bytes: u64) -> Result<(usize, u64), Error> {
+        let entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|it| it.bytes).sum();
+        let mut n = 0;
+        for entry in &entries {
+            if total <= bytes {
+                break;
+            }
+            Cache::remove(entry)?;
+            total -= entry.bytes;
+            n += 1;
+        }
+        Ok((n, total))
+    }
+
+    pub fn clear_cache(&self) -> Result<(), Error> {
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(it) => it,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(Error::Cache((self.dir.clone(), e))),
+        };
+
+        for entry in dir {
+            let path = entry.map_err(Error::in_cache(&self.dir))?.path();
+            let res =
+                if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+            res.map_err(Error::in_cache(path))?;
+        }
+        Ok(())
+    }
+}
+
+impl TileCache for Cache {
+    fn read(&mut self, key: &str) -> Option<DynamicImage> {
+        let path = self.path(key);
+        match Cache::read_checked(&path) {
+            Some((img, _)) => {
+                self.hits += 1;
+                // Failing to mark the entry as used only makes it evicted earlier
+                let _ = fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|it| it.set_modified(SystemTime::now()));
+                Some(img)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn write(&mut self, key: &str, img: &DynamicImage) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir).map_err(Error::in_cache(&self.dir))?;
+        let path = self.path(key);
+        let mut data = vec![];
+        img.write_to(&mut data, ImageOutputFormat::PNG)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+            .map_err(Error::in_cache(&path))?;
+        // The image is renamed first, a reader in between sees the old checksum and misses
+        write_atomic(&path, &data).map_err(Error::in_cache(&path))?;
+
+        let now = to_secs(SystemTime::now());
+        let meta = Meta {
+            source: self.source.clone(),
+            input: self.input.clone(),
+            key: Some(key.to_owned()),
+            version: Some(KEY_VERSION.to_owned()),
+            algorithm: Some(ALGORITHM_VERSION),
+            created: Some(now),
+            checksum: Some(checksum(&data)),
+        };
+        let meta_bytes = meta.write(&path.with_extension("txt"))?;
+
+        if let Some(max) = self.max_size {
+            // Overwritten entries are counted twice, which only evicts a bit earlier
+            let size = match self.size {
+                Some(size) => size + data.len() as u64 + meta_bytes,
+                None => self.entries()?.iter().map(|it| it.bytes).sum(),
+            };
+            self.size = Some(if size > max { self.evict(max)?.1 } else { size });
+        }
+        Ok(())
+    }
+
+    fn set_input(&mut self, path: &str, hash: &str) {
+        self.source = Some(path.to_owned());
+        self.input = Some(hash.to_owned());
+
+        if !self.swept {
+            self.swept = true;
+            // Incompatible entries are never read, failing to remove them only wastes space
+            let _ = self.remove_incompatible();
+        }
+    }
+}
+
+impl Drop for Cache {
+    fn drop(&mut self) {
+        let (hits, misses) = (self.hits, self.misses);
+        if hits + misses > 0 && fs::create_dir_all(&self.dir).is_ok() {
+            // Statistics are informative only, there is nothing to do on failure
+            let text = format!("{} {}\n", hits, misses);
+            let _ = write_atomic(&self.dir.join(STATS_FILE), text.as_bytes());
+        }
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty cache directory, unique to a test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("omega-tile-fs-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn set_last_used(cache: &Cache, key: &str, ago: Duration) {
+        let file = fs::OpenOptions::new().write(true).open(cache.path(key)).unwrap();
+        file.set_modified(SystemTime::now() - ago).unwrap();
+    }
+
+    #[test]
+    fn corrupted_image_is_a_miss() {
+        let dir = temp_dir("corrupted");
+        let img = DynamicImage::new_rgb8(4, 4);
+        let mut cache = Cache::with_dir(&dir);
+        cache.write("a", &img).unwrap();
+        fs::write(cache.path("a"), b"not a png").unwrap();
+
+        assert!(cache.read("a").is_none());
+        assert_eq!((cache.hits, cache.misses), (0, 1));
+        // The build writes the image again
+        cache.write("a", &img).unwrap();
+        assert!(cache.read("a").is_some());
+        drop(cache);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evicts_least_recently_read() {
+        let dir = temp_dir("evict");
+        let img = DynamicImage::new_rgb8(4, 4);
+        let mut cache = Cache::with_dir(&dir);
+        for key in &["a", "b", "c"] {
+            cache.write(key, &img).unwrap();
+        }
+        set_last_used(&cache, "a", Duration::from_secs(300));
+        set_last_used(&cache, "b", Duration::from_secs(200));
+        set_last_used(&cache, "c", Duration::from_secs(100));
+        // Reading the oldest entry makes it the most recently used
+        assert!(cache.read("a").is_some());
+
+        let total: u64 = cache.entries().unwrap().iter().map(|it| it.bytes).sum();
+        assert_eq!(cache.evict_to(total - 1).unwrap(), 1);
+        assert!(!cache.path("b").exists());
+        assert!(cache.read("a").is_some());
+        assert!(cache.read("c").is_some());
+        drop(cache);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stats_persist_across_drop() {
+        let dir = temp_dir("stats");
+        let mut cache = Cache::with_dir(&dir);
+        cache.write("a", &DynamicImage::new_rgb8(4, 4)).unwrap();
+        assert!(cache.read("a").is_some());
+        assert!(cache.read("b").is_none());
+        drop(cache);
+
+        let stats = Cache::with_dir(&dir).stats().unwrap();
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.last_run, Some((1, 1)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prune_older_than() {
+        let dir = temp_dir("prune");
+        let img = DynamicImage::new_rgb8(4, 4);
+        let mut cache = Cache::with_dir(&dir);
+        cache.write("old", &img).unwrap();
+        cache.write("recent", &img).unwrap();
+        set_last_used(&cache, "old", Duration::from_secs(2 * 60 * 60));
+        set_last_used(&cache, "recent", Duration::from_secs(30 * 60));
+
+        assert_eq!(cache.prune_older_than(Duration::from_secs(60 * 60)).unwrap(), 1);
+        assert!(!cache.path("old").exists());
+        assert!(!cache.path("old").with_extension("txt").exists());
+        assert!(cache.path("recent").exists());
+        drop(cache);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entries_record_their_build() {
+        let dir = temp_dir("entries");
+        let mut cache = Cache::with_dir(&dir);
+        cache.set_input("input.png", "1234");
+        cache.write("omega-tile+4+4+tile", &DynamicImage::new_rgb8(4, 4)).unwrap();
+
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.source.as_deref(), Some("input.png"));
+        assert_eq!(entry.input.as_deref(), Some("1234"));
+        assert_eq!(entry.key.as_deref(), Some("omega-tile+4+4+tile"));
+        assert_eq!(entry.version.as_deref(), Some(KEY_VERSION));
+        assert!(entry.compatible);
+        assert!(entry.created.is_some());
+        drop(cache);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hit_rate() {
+        let stats = |last_run| CacheStats { entries: 0, bytes: 0, last_run };
+        assert_eq!(stats(None).hit_rate(), None);
+        assert_eq!(stats(Some((0, 0))).hit_rate(), None);
+        assert_eq!(stats(Some((3, 1))).hit_rate(), Some(0.75));
+    }
+
+    #[test]
+    fn other_versions_removed_on_set_input() {
+        let dir = temp_dir("versions");
+        let img = DynamicImage::new_rgb8(4, 4);
+        let mut cache = Cache::with_dir(&dir);
+        for key in &["current", "algorithm", "version"] {
+            cache.write(key, &img).unwrap();
+        }
+        let rewrite_meta = |key: &str, from: &str, to: &str| {
+            let path = cache.path(key).with_extension("txt");
+            let text = fs::read_to_string(&path).unwrap();
+            assert!(text.contains(from));
+            fs::write(&path, text.replace(from, to)).unwrap();
+        };
+        rewrite_meta("algorithm", &format!("algorithm={}", ALGORITHM_VERSION), "algorithm=0");
+        rewrite_meta("version", &format!("version={}", KEY_VERSION), "version=omega-tile-0.0.0");
+
+        // Entries of another version are never read, and removed by the next build
+        let mut cache = Cache::with_dir(&dir);
+        assert!(cache.read("algorithm").is_none());
+        cache.set_input("input.png", "1234");
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key.as_deref(), Some("current"));
+        drop(cache);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use super::{key_prefix, TileCache};
+use crate::error::Error;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use texture_synthesis as ts;
+use ts::image::{DynamicImage, GenericImageView};
+
+/// Images kept in memory for the lifetime of the cache.
+///
+/// Clones share the same images, so a cache given to a build running on another
+/// thread is still filled for the next builds.
+#[derive(Clone, Default)]
+pub struct MemoryCache {
+    inner: Arc<Mutex<Images>>,
+}
+
+#[derive(Default)]
+struct Images {
+    /// Image of each key, with the time it was last used
+    images: HashMap<String, Entry>,
+    /// Size of the pixels of every image
+    bytes: u64,
+    /// Size budget in bytes, least recently used images are evicted above it
+    max_size: Option<u64>,
+    /// Incremented at each access, to order the images by last use
+    clock: u64,
+}
+
+struct Entry {
+    img: DynamicImage,
+    used: u64,
+    /// Key prefix of the version which made the image, like a packed cache record
+    version: String,
+}
+
+/// Size of the pixels of an image
+fn image_bytes(img: &DynamicImage) -> u64 {
+    let channels = match img {
+        DynamicImage::ImageLuma8(_) => 1,
+        DynamicImage::ImageLumaA8(_) => 2,
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageBgr8(_) => 3,
+        DynamicImage::ImageRgba8(_) | DynamicImage::ImageBgra8(_) => 4,
+    };
+    let (w, h) = img.dimensions();
+    u64::from(w) * u64::from(h) * channels
+}
+
+impl Images {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Remove the least recently used images until they fit in the size budget
+    fn evict(&mut self) {
+        let max = match self.max_size {
+            Some(it) => it,
+            None => return,
+        };
+        while self.bytes > max {
+            let oldest = self.images.iter().min_by_key(|(_, it)| it.used);
+            let key = match oldest {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+            self.remove(&key);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.images.remove(key) {
+            self.bytes -= image_bytes(&entry.img);
+        }
+    }
+}
+
+impl MemoryCache {
+    pub fn new() -> MemoryCache {
+        MemoryCache::default()
+    }
+
+    /// Evict the least recently used images when the cache grows above `bytes`
+    pub fn with_max_size(self, bytes: u64) -> MemoryCache {
+        {
+            let mut inner = self.lock();
+            inner.max_size = Some(bytes);
+            inner.evict();
+        }
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().images.is_empty()
+    }
+
+    /// Size of the pixels of the cached images
+    pub fn bytes(&self) -> u64 {
+        self.lock().bytes
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.images.clear();
+        inner.bytes = 0;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Images> {
+        // A panic while holding the lock can't leave the map half updated
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl TileCache for MemoryCache {
+    fn read(&mut self, key: &str) -> Option<DynamicImage> {
+        let mut inner = self.lock();
+        let now = inner.tick();
+        let entry = inner.images.get_mut(key)?;
+        // Images made by another version are never read
+        if entry.version != key_prefix() {
+            inner.remove(key);
+            return None;
+        }
+        entry.used = now;
+        Some(entry.img.clone())
+    }
+
+    fn write(&mut self, key: &str, img: &DynamicImage) -> Result<(), Error> {
+        let mut inner = self.lock();
+        let now = inner.tick();
+        inner.bytes += image_bytes(img);
+        let entry = Entry { img: img.clone(), used: now, version: key_prefix() };
+        if let Some(old) = inner.images.insert(key.to_owned(), entry) {
+            inner.bytes -= image_bytes(&old.img);
+        }
+        inner.evict();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let img = DynamicImage::new_rgba8(4, 4);
+        let mut cache = MemoryCache::new().with_max_size(2 * image_bytes(&img));
+        cache.write("a", &img).unwrap();
+        cache.write("b", &img).unwrap();
+        assert!(cache.read("a").is_some());
+        cache.write("c", &img).unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.bytes(), 2 * 64);
+        assert!(cache.read("b").is_none());
+        assert!(cache.read("a").is_some());
+        assert!(cache.read("c").is_some());
+    }
+
+    #[test]
+    fn drops_other_versions() {
+        let img = DynamicImage::new_rgba8(4, 4);
+        let mut cache = MemoryCache::new();
+        cache.write("a", &img).unwrap();
+        cache.lock().images.get_mut("a").unwrap().version = "omega-tile-0.0.0+a1".to_string();
+
+        assert!(cache.read("a").is_none());
+        assert!(cache.is_empty());
+        assert_eq!(cache.bytes(), 0);
+    }
+}
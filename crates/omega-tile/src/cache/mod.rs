@@ -0,0 +1,71 @@
+mod fs;
+mod memory;
+mod packed;
+
+pub use self::fs::{Cache, CacheEntry, CacheStats};
+pub use self::memory::MemoryCache;
+pub use self::packed::PackedCache;
+
+use crate::error::Error;
+use sha2::{Digest, Sha256};
+use texture_synthesis as ts;
+use ts::image::{DynamicImage, GenericImageView};
+
+/// Version of omega-tile, part of the cache keys so images made by another
+/// version are never read
+pub(crate) const KEY_VERSION: &str = concat!("omega-tile-", env!("CARGO_PKG_VERSION"));
+
+/// Version of the code making the cached images, like `merge_samples` or
+/// `build_mask`. Increment it when their output changes, so images made by the
+/// previous code are not used anymore.
+pub(crate) const ALGORITHM_VERSION: u32 = 2;
+
+/// Prefix of every cache key
+pub(crate) fn key_prefix() -> String {
+    format!("{}+a{}", KEY_VERSION, ALGORITHM_VERSION)
+}
+
+/// Storage of the images synthesized by a build, so they are not synthesized
+/// again by the next builds with the same inputs and parameters.
+///
+/// Keys are made by the build from everything the image depends on, a backend
+/// only has to return the image last written with the same key.
+pub trait TileCache {
+    /// Image written with `key`, `None` if there is none or it can't be read
+    fn read(&mut self, key: &str) -> Option<DynamicImage>;
+
+    fn write(&mut self, key: &str, img: &DynamicImage) -> Result<(), Error>;
+
+    /// Called at the start of a build with the path and the hash of its primary input
+    fn set_input(&mut self, _path: &str, _hash: &str) {}
+}
+
+impl<C: TileCache + ?Sized> TileCache for Box<C> {
+    fn read(&mut self, key: &str) -> Option<DynamicImage> {
+        (**self).read(key)
+    }
+
+    fn write(&mut self, key: &str, img: &DynamicImage) -> Result<(), Error> {
+        (**self).write(key, img)
+    }
+
+    fn set_input(&mut self, path: &str, hash: &str) {
+        (**self).set_input(path, hash)
+    }
+}
+
+/// Hex encoded SHA-256 of the dimensions, color type and pixels of an image
+pub(crate) fn hash_image(img: &DynamicImage) -> String {
+    let mut hasher = Sha256::new();
+    let (w, h) = img.dimensions();
+    hasher.input(format!("{}x{}+{:?}+", w, h, img.color()).as_bytes());
+    hasher.input(img.raw_pixels());
+    hex::encode(hasher.result())
+}
+
+/// Hex encoded SHA-256 of a key, short enough for a file name
+pub(crate) fn key_hash(key: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(key.as_bytes());
+    hex::encode(hasher.result())
+}
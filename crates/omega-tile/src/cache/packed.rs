@@ -0,0 +1,440 @@
+use super::{key_hash, key_prefix, TileCache};
+use crate::error::Error;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use texture_synthesis as ts;
+use ts::image::{DynamicImage, ImageOutputFormat};
+
+/// First bytes of a packed cache file
+const MAGIC: &[u8; 8] = b"OMGTPAK2";
+/// First bytes of the packed cache files of every format, older ones are emptied
+const MAGIC_PREFIX: &[u8; 7] = b"OMGTPAK";
+/// Size of the checksum of a record
+const CHECKSUM_LEN: usize = 32;
+
+/// Images stored in a single file, which can be copied to another machine.
+///
+/// Records are only appended, a key written again is looked up in its last record.
+/// Records cut by an interrupted write, failing their checksum or made by another
+/// version of omega-tile or of its algorithms are skipped when the file is opened.
+/// It is then compacted if there are records of another version, or more stale
+/// records than live ones.
+///
+/// Unlike a cache directory, it can't be used by several processes at once: the
+/// file is locked while open, and opening it again fails.
+///
+/// The file is made of the magic bytes followed by records of:
+/// - length of the key hash, u32 little endian
+/// - key hash
+/// - length of the version, u32 little endian
+/// - version, the prefix of the keys
+/// - length of the image, u32 little endian
+/// - SHA-256 of the key hash, the version and the image
+/// - png image
+pub struct PackedCache {
+    path: PathBuf,
+    file: File,
+    /// Offset and length of the image of each key hash
+    index: HashMap<String, (u64, u32)>,
+    /// Size of the records replaced by a later record of the same key, damaged or
+    /// of another version
+    stale: u64,
+    /// Whether some records were made by another version
+    outdated: bool,
+}
+
+fn invalid_data<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn read_u32(file: &mut File) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    file.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Size of the record of an image of this version
+fn record_len(key: &str, data_len: u32) -> u64 {
+    12 + CHECKSUM_LEN as u64 + key.len() as u64 + key_prefix().len() as u64 + u64::from(data_len)
+}
+
+fn checksum(key: &[u8], version: &[u8], data: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.input(key);
+    hasher.input(version);
+    hasher.input(data);
+    let mut res = [0; CHECKSUM_LEN];
+    res.copy_from_slice(&hasher.result());
+    res
+}
+
+/// Record of an image of this version, up to the image itself
+fn record_header(key: &str, data: &[u8]) -> Vec<u8> {
+    let version = key_prefix();
+    let mut record = Vec::with_capacity(record_len(key, data.len() as u32) as usize);
+    record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    record.extend_from_slice(key.as_bytes());
+    record.extend_from_slice(&(version.len() as u32).to_le_bytes());
+    record.extend_from_slice(version.as_bytes());
+    record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    record.extend_from_slice(&checksum(key.as_bytes(), version.as_bytes(), data));
+    record
+}
+
+/// Take the lock of a cache file, or fail if another process holds it
+fn lock(file: &File) -> io::Result<()> {
+    match file.try_lock() {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "The packed cache is already used by another process",
+        )),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Record of the file, with its version and the offset and length of its image
+enum Record {
+    Valid(String, String, u64, u32),
+    /// A record which fails its checksum, with its size
+    Damaged(u64),
+}
+
+impl PackedCache {
+    /// Open the cache file at `path`, or create it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<PackedCache, Error> {
+        let path = path.as_ref();
+        let mut cache = PackedCache::load(path.to_owned()).map_err(Error::in_cache(path))?;
+        if cache.outdated || cache.stale > cache.live_len() {
+            cache.compact()?;
+        }
+        Ok(cache)
+    }
+
+    fn load(path: PathBuf) -> io::Result<PackedCache> {
+        // The records of an existing file are kept
+        let mut file =
+            OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        lock(&file)?;
+        let mut len = file.metadata()?.len();
+
+        let mut index = HashMap::new();
+        let mut stale = 0;
+        let mut outdated = false;
+        if len > 0 {
+            let mut magic = [0; 8];
+            file.read_exact(&mut magic)?;
+            if &magic[..MAGIC_PREFIX.len()] != MAGIC_PREFIX {
+                return Err(invalid_data("Not a packed cache file"));
+            }
+            // The images of an older format are dropped, as any other stale image
+            if &magic != MAGIC {
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                len = 0;
+            }
+        }
+        if len == 0 {
+            file.write_all(MAGIC)?;
+        } else {
+            let version = key_prefix();
+            let mut pos = MAGIC.len() as u64;
+            while pos < len {
+                let record = (|| -> io::Result<Record> {
+                    // Lengths are checked against the file before allocating, as a
+                    // damaged record may have any length
+                    let read_bytes = |file: &mut File| -> io::Result<Vec<u8>> {
+                        let n = read_u32(file)?;
+                        if file.stream_position()? + u64::from(n) > len {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        let mut bytes = vec![0; n as usize];
+                        file.read_exact(&mut bytes)?;
+                        Ok(bytes)
+                    };
+                    let key = read_bytes(&mut file)?;
+                    let version = read_bytes(&mut file)?;
+                    let data_len = read_u32(&mut file)?;
+                    let mut sum = [0; CHECKSUM_LEN];
+                    file.read_exact(&mut sum)?;
+                    let offset = file.stream_position()?;
+                    if offset + u64::from(data_len) > len {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    let mut data = vec![0; data_len as usize];
+                    file.read_exact(&mut data)?;
+
+                    if checksum(&key, &version, &data) != sum {
+                        return Ok(Record::Damaged(offset + u64::from(data_len) - pos));
+                    }
+                    match (String::from_utf8(key), String::from_utf8(version)) {
+                        (Ok(key), Ok(version)) => Ok(Record::Valid(key, version, offset, data_len)),
+                        _ => Ok(Record::Damaged(offset + u64::from(data_len) - pos)),
+                    }
+                })();
+
+                match record {
+                    // Never read, and dropped by the compaction
+                    Ok(Record::Valid(_, ref v, offset, data_len)) if *v != version => {
+                        stale += offset + u64::from(data_len) - pos;
+                        outdated = true;
+                        pos = offset + u64::from(data_len);
+                    }
+                    Ok(Record::Valid(key, _, offset, data_len)) => {
+                        if let Some((_, old_len)) = index.insert(key.clone(), (offset, data_len)) {
+                            stale += record_len(&key, old_len);
+                        }
+                        pos = offset + u64::from(data_len);
+                    }
+                    // Left in the file until it is compacted
+                    Ok(Record::Damaged(size)) => {
+                        stale += size;
+                        pos += size;
+                    }
+                    // A record cut by an interrupted write, it is overwritten by the next one
+                    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                        file.set_len(pos)?;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(PackedCache { path, file, index, stale, outdated })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of distinct images in the cache
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Size of the records still used
+    fn live_len(&self) -> u64 {
+        self.index.iter().map(|(key, &(_, len))| record_len(key, len)).sum()
+    }
+
+    /// Rewrite the file with only the last record of each key, returns the number
+    /// of bytes freed
+    pub fn compact(&mut self) -> Result<u64, Error> {
+        let path = self.path.clone();
+        self.rewrite().map_err(Error::in_cache(path))
+    }
+
+    fn rewrite(&mut self) -> io::Result<u64> {
+        let old_len = self.file.metadata()?.len();
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        // Records keep their order, the most recently written last
+        let mut records: Vec<_> =
+            self.index.iter().map(|(key, &(offset, len))| (offset, len, key.clone())).collect();
+        records.sort();
+
+        let file =
+            OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&tmp)?;
+        lock(&file)?;
+        let mut out = BufWriter::new(&file);
+        out.write_all(MAGIC)?;
+        let mut index = HashMap::new();
+        let mut pos = MAGIC.len() as u64;
+        for (offset, len, key) in records {
+            let mut data = vec![0; len as usize];
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(&mut data)?;
+
+            let header = record_header(&key, &data);
+            out.write_all(&header)?;
+            out.write_all(&data)?;
+            index.insert(key, (pos + header.len() as u64, len));
+            pos += header.len() as u64 + u64::from(len);
+        }
+        out.flush()?;
+        drop(out);
+
+        // The new file is already locked, the lock of the old one goes with it
+        fs::rename(&tmp, &self.path)?;
+        self.file = file;
+        self.index = index;
+        self.stale = 0;
+        self.outdated = false;
+        Ok(old_len.saturating_sub(pos))
+    }
+
+    fn read_record(&mut self, offset: u64, len: u32) -> io::Result<DynamicImage> {
+        let mut data = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut data)?;
+        ts::image::load_from_memory(&data).map_err(invalid_data)
+    }
+
+    fn append(&mut self, key: &str, img: &DynamicImage) -> io::Result<()> {
+        let mut data = vec![];
+        img.write_to(&mut data, ImageOutputFormat::PNG).map_err(invalid_data)?;
+        let hash = key_hash(key);
+
+        let mut record = record_header(&hash, &data);
+        let offset = self.file.seek(SeekFrom::End(0))? + record.len() as u64;
+        record.extend_from_slice(&data);
+        self.file.write_all(&record)?;
+
+        if let Some((_, old_len)) = self.index.insert(hash.clone(), (offset, data.len() as u32)) {
+            self.stale += record_len(&hash, old_len);
+        }
+        Ok(())
+    }
+}
+
+impl TileCache for PackedCache {
+    fn read(&mut self, key: &str) -> Option<DynamicImage> {
+        let (offset, len) = *self.index.get(&key_hash(key))?;
+        self.read_record(offset, len).ok()
+    }
+
+    fn write(&mut self, key: &str, img: &DynamicImage) -> Result<(), Error> {
+        let path = self.path.clone();
+        self.append(key, img).map_err(Error::in_cache(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ts::image::GenericImageView;
+
+    #[test]
+    fn compacts_stale_records() {
+        let path =
+            std::env::temp_dir().join(format!("omega-tile-packed-{}.bin", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let small = DynamicImage::new_rgb8(4, 4);
+        let large = DynamicImage::ImageRgb8(ts::image::RgbImage::from_fn(64, 64, |x, y| {
+            ts::image::Rgb([(x * 7) as u8, (y * 13) as u8, (x * y) as u8])
+        }));
+        {
+            let mut cache = PackedCache::open(&path).unwrap();
+            cache.write("a", &large).unwrap();
+            cache.write("a", &small).unwrap();
+            cache.write("b", &small).unwrap();
+        }
+        let len = fs::metadata(&path).unwrap().len();
+
+        // The stale record of "a" is larger than the live ones
+        let mut cache = PackedCache::open(&path).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < len);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.read("a").map(|it| it.dimensions()), Some((4, 4)));
+        assert!(cache.read("b").is_some());
+        drop(cache);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refuses_huge_lengths() {
+        let path =
+            std::env::temp_dir().join(format!("omega-tile-packed-bad-{}.bin", std::process::id()));
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &data).unwrap();
+
+        // A damaged record is dropped like an interrupted write
+        let cache = PackedCache::open(&path).unwrap();
+        assert!(cache.is_empty());
+        assert_eq!(fs::metadata(&path).unwrap().len(), MAGIC.len() as u64);
+        drop(cache);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn skips_damaged_records() {
+        let path = std::env::temp_dir()
+            .join(format!("omega-tile-packed-damaged-{}.bin", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let img = DynamicImage::new_rgb8(4, 4);
+        {
+            let mut cache = PackedCache::open(&path).unwrap();
+            cache.write("a", &img).unwrap();
+            cache.write("b", &img).unwrap();
+            cache.write("c", &img).unwrap();
+        }
+
+        // Flip the last byte of the image of "b", the records have the same size
+        let mut data = fs::read(&path).unwrap();
+        let b_end = MAGIC.len() + 2 * (data.len() - MAGIC.len()) / 3;
+        data[b_end - 1] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+
+        let mut cache = PackedCache::open(&path).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(cache.read("a").is_some());
+        assert!(cache.read("b").is_none());
+        assert!(cache.read("c").is_some());
+        drop(cache);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refuses_concurrent_use() {
+        let path =
+            std::env::temp_dir().join(format!("omega-tile-packed-lock-{}.bin", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let cache = PackedCache::open(&path).unwrap();
+        match PackedCache::open(&path) {
+            Err(Error::Cache((_, e))) => assert_eq!(e.kind(), io::ErrorKind::WouldBlock),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("the cache file is opened twice"),
+        }
+
+        // The lock is released with the cache
+        drop(cache);
+        assert!(PackedCache::open(&path).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn drops_other_versions() {
+        let path = std::env::temp_dir()
+            .join(format!("omega-tile-packed-version-{}.bin", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let img = DynamicImage::new_rgb8(4, 4);
+        PackedCache::open(&path).unwrap().write("a", &img).unwrap();
+
+        // Append a record of "b" as made by an older version
+        let mut data = vec![];
+        img.write_to(&mut data, ImageOutputFormat::PNG).unwrap();
+        let (key, version) = (key_hash("b"), "omega-tile-0.0.0+a1");
+        let mut record = vec![];
+        record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        record.extend_from_slice(key.as_bytes());
+        record.extend_from_slice(&(version.len() as u32).to_le_bytes());
+        record.extend_from_slice(version.as_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&checksum(key.as_bytes(), version.as_bytes(), &data));
+        record.extend_from_slice(&data);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&record).unwrap();
+        drop(file);
+        let len = fs::metadata(&path).unwrap().len();
+
+        // The file is compacted without it, even though it is smaller than the live ones
+        let mut cache = PackedCache::open(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), len - record.len() as u64);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.read("a").is_some());
+        assert!(cache.read("b").is_none());
+        drop(cache);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+use crate::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use texture_synthesis as ts;
+use ts::image::RgbaImage;
+
+/// Shared flag to stop a build from another thread.
+///
+/// A running synthesis stops at its next progress update once the token is
+/// cancelled, and the build returns `Error::Cancelled`, so the cache only holds
+/// finished images.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// How often a waiting build checks its token
+const POLL: Duration = Duration::from_millis(20);
+
+/// Unwinds the worker out of a cancelled synthesis
+struct Stop;
+
+enum Message {
+    Progress(RgbaImage, (usize, usize), (usize, usize)),
+    Done(ts::GeneratedImage),
+}
+
+/// Run a session on a worker thread, forwarding its progress, until it finishes
+/// or the token is cancelled.
+///
+/// texture-synthesis can not be interrupted, so the worker unwinds out of it from
+/// the progress callback. A cancelled session only returns once its worker has
+/// ended, which may take the time of a synthesis stage.
+pub(crate) fn run_session(
+    session: ts::Session,
+    mut progress: Box<dyn ts::GeneratorProgress>,
+    token: &CancelToken,
+) -> Result<ts::GeneratedImage, Error> {
+    let (tx, rx) = mpsc::channel();
+    let cancel = token.clone();
+    let worker = thread::spawn(move || {
+        let progress_tx = tx.clone();
+        let forward = move |info: ts::ProgressUpdate<'_>| {
+            if cancel.is_cancelled() {
+                // Unlike `panic!`, doesn't run the panic hook
+                std::panic::resume_unwind(Box::new(Stop));
+            }
+            let total = (info.total.current, info.total.total);
+            let stage = (info.stage.current, info.stage.total);
+            let _ = progress_tx.send(Message::Progress(info.image.clone(), total, stage));
+        };
+        let _ = tx.send(Message::Done(session.run(Some(Box::new(forward)))));
+    });
+
+    let stopped = |panic: Box<dyn std::any::Any + Send>| {
+        if panic.is::<Stop>() {
+            Error::Cancelled
+        } else {
+            std::panic::resume_unwind(panic)
+        }
+    };
+
+    loop {
+        if token.is_cancelled() {
+            // Also when the worker finished before seeing the token
+            return Err(worker.join().err().map_or(Error::Cancelled, stopped));
+        }
+        match rx.recv_timeout(POLL) {
+            Ok(Message::Progress(image, total, stage)) => progress.update(ts::ProgressUpdate {
+                image: &image,
+                total: ts::ProgressStat { current: total.0, total: total.1 },
+                stage: ts::ProgressStat { current: stage.0, total: stage.1 },
+            }),
+            Ok(Message::Done(generated)) => return Ok(generated),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => match worker.join() {
+                Err(panic) => return Err(stopped(panic)),
+                Ok(()) => unreachable!("synthesis thread ended without a result"),
+            },
+        }
+    }
+}
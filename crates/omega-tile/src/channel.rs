@@ -0,0 +1,68 @@
+use crate::{Rgba16Image, WTileSet};
+use texture_synthesis as ts;
+use ts::image::DynamicImage;
+
+/// A named image of a material, e.g. `albedo` or `normal`
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub name: String,
+    pub path: String,
+}
+
+/// Aligned images of one material.
+///
+/// The first channel drives the synthesis, the other channels reproduce
+/// exactly the same patch placement, so every channel gets the same tile ids.
+#[derive(Debug, Clone)]
+pub struct Channels {
+    channels: Vec<Channel>,
+}
+
+impl Channels {
+    pub fn new(name: &str, path: &str) -> Channels {
+        Channels { channels: vec![Channel { name: name.to_owned(), path: path.to_owned() }] }
+    }
+
+    pub fn with_channel(mut self, name: &str, path: &str) -> Channels {
+        self.channels.push(Channel { name: name.to_owned(), path: path.to_owned() });
+        self
+    }
+
+    pub fn primary(&self) -> &Channel {
+        &self.channels[0]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Channel> {
+        self.channels.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+}
+
+impl From<&str> for Channels {
+    fn from(path: &str) -> Channels {
+        Channels::new("main", path)
+    }
+}
+
+/// Tiles and samples built for one channel
+pub struct ChannelTiles {
+    pub name: String,
+    /// For a 16 bits per channel input, the high bytes of `deep`
+    pub tiles: WTileSet,
+    pub samples: Vec<DynamicImage>,
+    /// Tiles and samples with 16 bits per channel, if the input has them
+    pub deep: Option<DeepTiles>,
+}
+
+/// 16 bits per channel images of a channel, in the same order as its tiles and samples
+pub struct DeepTiles {
+    pub tiles: Vec<Rgba16Image>,
+    pub samples: Vec<Rgba16Image>,
+}
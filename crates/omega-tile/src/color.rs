@@ -0,0 +1,77 @@
+use crate::error::Error;
+use texture_synthesis as ts;
+use ts::image::{DynamicImage, Rgba, RgbaImage};
+
+/// Color channels of the built tiles and samples
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum ColorOutput {
+    #[default]
+    Rgb,
+    /// Keep the alpha channel, transparency is also taken into account when
+    /// choosing the patches
+    Rgba,
+}
+
+impl std::str::FromStr for ColorOutput {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb" => Ok(ColorOutput::Rgb),
+            "rgba" => Ok(ColorOutput::Rgba),
+            _ => Err(Error::ParseError("Not a valid color output".into())),
+        }
+    }
+}
+
+impl std::fmt::Display for ColorOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let s = match self {
+            ColorOutput::Rgb => "rgb",
+            ColorOutput::Rgba => "rgba",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+impl ColorOutput {
+    /// Allocate an empty image with these color channels
+    pub fn new_image(self, w: u32, h: u32) -> DynamicImage {
+        match self {
+            ColorOutput::Rgb => DynamicImage::new_rgb8(w, h),
+            ColorOutput::Rgba => DynamicImage::new_rgba8(w, h),
+        }
+    }
+
+    /// Convert an image to these color channels
+    pub fn convert(self, img: &DynamicImage) -> DynamicImage {
+        match (self, img) {
+            (ColorOutput::Rgb, DynamicImage::ImageRgb8(_))
+            | (ColorOutput::Rgba, DynamicImage::ImageRgba8(_)) => img.clone(),
+            (ColorOutput::Rgb, _) => DynamicImage::ImageRgb8(img.to_rgb()),
+            (ColorOutput::Rgba, _) => DynamicImage::ImageRgba8(img.to_rgba()),
+        }
+    }
+
+    /// Image given to texture-synthesis in place of `img`.
+    ///
+    /// Texture-synthesis only compares the color channels, so with alpha the
+    /// colors are premultiplied to make transparent pixels match each other.
+    pub(crate) fn synthesis_image(self, img: &DynamicImage) -> DynamicImage {
+        match self {
+            ColorOutput::Rgb => img.clone(),
+            ColorOutput::Rgba => DynamicImage::ImageRgba8(premultiply(&img.to_rgba())),
+        }
+    }
+}
+
+fn premultiply(img: &RgbaImage) -> RgbaImage {
+    let (w, h) = img.dimensions();
+    RgbaImage::from_fn(w, h, |x, y| {
+        let p = img.get_pixel(x, y);
+        let a = u32::from(p[3]);
+        let mul = |c: u8| ((u32::from(c) * a + 127) / 255) as u8;
+        Rgba([mul(p[0]), mul(p[1]), mul(p[2]), p[3]])
+    })
+}
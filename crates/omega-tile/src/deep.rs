@@ -0,0 +1,172 @@
+use crate::color::ColorOutput;
+use crate::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+use texture_synthesis as ts;
+use ts::image::{
+    png::PNGEncoder, ColorType, DynamicImage, ImageBuffer, ImageError, ImageFormat, Rgba, RgbaImage,
+};
+
+/// An image with 16 bits per channel
+pub type Rgba16Image = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+/// Reader of the PNG at `path`, which keeps 16 bits samples unlike the decoder of
+/// `image`, `None` if the file is not a PNG
+fn png_reader(path: &Path) -> Result<Option<(png::OutputInfo, png::Reader<impl Read>)>, Error> {
+    match ImageFormat::from_path(path) {
+        Ok(ImageFormat::PNG) => {}
+        _ => return Ok(None),
+    }
+
+    let decode_err = |e: ImageError| Error::ImageDecode((path.to_owned(), e));
+    let file = File::open(path).map_err(|e| decode_err(e.into()))?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let res = decoder.read_info().map_err(|e| decode_err(e.into()))?;
+    Ok(Some(res))
+}
+
+/// Whether the image at `path` is a 16 bits per channel PNG
+pub(crate) fn is_deep(path: &Path) -> bool {
+    match png_reader(path) {
+        Ok(Some((info, _))) => info.bit_depth == png::BitDepth::Sixteen,
+        _ => false,
+    }
+}
+
+/// Open a 16 bits per channel PNG, `None` if the image is not one
+pub(crate) fn open_deep_image(path: &Path) -> Result<Option<Rgba16Image>, Error> {
+    let (info, mut reader) = match png_reader(path)? {
+        Some((info, reader)) if info.bit_depth == png::BitDepth::Sixteen => (info, reader),
+        _ => return Ok(None),
+    };
+
+    let channels = match reader.output_color_type().0 {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        _ => 4,
+    };
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data).map_err(|e| Error::ImageDecode((path.to_owned(), e.into())))?;
+
+    // PNG samples are big endian
+    let (w, h) = (info.width, info.height);
+    let at = |i: usize| u16::from_be_bytes([data[2 * i], data[2 * i + 1]]);
+    let img = Rgba16Image::from_fn(w, h, |x, y| {
+        let i = (y as usize * w as usize + x as usize) * channels;
+        match channels {
+            1 => Rgba([at(i), at(i), at(i), u16::MAX]),
+            2 => Rgba([at(i), at(i), at(i), at(i + 1)]),
+            3 => Rgba([at(i), at(i + 1), at(i + 2), u16::MAX]),
+            _ => Rgba([at(i), at(i + 1), at(i + 2), at(i + 3)]),
+        }
+    });
+    Ok(Some(img))
+}
+
+/// Split an image into its high bytes, which texture-synthesis works on, and its
+/// low bytes, which follow the same patch placement as another channel
+pub(crate) fn split_bytes(img: &Rgba16Image) -> (DynamicImage, DynamicImage) {
+    let (w, h) = img.dimensions();
+    let plane = |f: fn(u16) -> u8| {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(w, h, |x, y| {
+            let p = img.get_pixel(x, y);
+            Rgba([f(p[0]), f(p[1]), f(p[2]), f(p[3])])
+        }))
+    };
+    (plane(|v| (v >> 8) as u8), plane(|v| v as u8))
+}
+
+/// Put back together the high and low bytes made by `split_bytes`
+pub(crate) fn join_bytes(
+    high: &DynamicImage,
+    low: &DynamicImage,
+    color: ColorOutput,
+) -> Rgba16Image {
+    let (high, low) = (high.to_rgba(), low.to_rgba());
+    let (w, h) = high.dimensions();
+    Rgba16Image::from_fn(w, h, |x, y| {
+        let (p, q) = (high.get_pixel(x, y), low.get_pixel(x, y));
+        let v = |c: usize| u16::from(p[c]) << 8 | u16::from(q[c]);
+        let alpha = match color {
+            ColorOutput::Rgb => u16::MAX,
+            ColorOutput::Rgba => v(3),
+        };
+        Rgba([v(0), v(1), v(2), alpha])
+    })
+}
+
+/// Save a 16 bits per channel PNG, with the color channels of `color`
+pub fn save_deep_image<Q>(img: &Rgba16Image, color: ColorOutput, path: Q) -> Result<(), Error>
+where
+    Q: AsRef<Path>,
+{
+    let channels = match color {
+        ColorOutput::Rgb => 3,
+        ColorOutput::Rgba => 4,
+    };
+    let mut data = Vec::with_capacity(img.len() / 4 * channels * 2);
+    for p in img.pixels() {
+        for c in 0..channels {
+            data.extend_from_slice(&p[c].to_be_bytes());
+        }
+    }
+
+    let (w, h) = img.dimensions();
+    let file = BufWriter::new(File::create(path)?);
+    let color_type = match color {
+        ColorOutput::Rgb => ColorType::RGB(16),
+        ColorOutput::Rgba => ColorType::RGBA(16),
+    };
+    PNGEncoder::new(file).encode(&data, w, h, color_type)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp((w, h): (u32, u32)) -> Rgba16Image {
+        Rgba16Image::from_fn(w, h, |x, y| {
+            let v = (y * w + x) as u16;
+            Rgba([v.wrapping_mul(257), v.wrapping_mul(31), v, u16::MAX - v])
+        })
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let img = ramp((16, 8));
+        let (high, low) = split_bytes(&img);
+        assert_eq!(join_bytes(&high, &low, ColorOutput::Rgba).into_raw(), img.into_raw());
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let img = ramp((16, 8));
+        let path = std::env::temp_dir().join(format!("omega-tile-deep-{}.png", std::process::id()));
+        save_deep_image(&img, ColorOutput::Rgba, &path).unwrap();
+        let opened = open_deep_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(opened.map(Rgba16Image::into_raw), Some(img.into_raw()));
+    }
+
+    #[test]
+    fn reduced_to_high_bytes() {
+        let img = ramp((16, 8));
+        let path =
+            std::env::temp_dir().join(format!("omega-tile-reduced-{}.png", std::process::id()));
+        save_deep_image(&img, ColorOutput::Rgba, &path).unwrap();
+        let reduced = crate::open_reduced_image(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let reduced = reduced.unwrap().to_rgba();
+        for (p, q) in img.pixels().zip(reduced.pixels()) {
+            assert_eq!(
+                q.0,
+                [(p[0] >> 8) as u8, (p[1] >> 8) as u8, (p[2] >> 8) as u8, (p[3] >> 8) as u8]
+            );
+        }
+    }
+}
@@ -1,31 +1,104 @@
+use std::path::PathBuf;
 use texture_synthesis as ts;
 
 #[derive(Debug)]
 pub enum Error {
-    TsError(ts::Error),
+    /// Texture synthesis failed
+    Synthesis(ts::Error),
     Io(std::io::Error),
+    /// The image at the path could not be decoded
+    ImageDecode((PathBuf, ts::image::ImageError)),
+    /// The inputs can't be used together, with the reason
+    InvalidInput(String),
     SizeMismatch,
+    EmptyImage,
+    /// The image can not be split into tiles with even sides
+    OddSize((u32, u32)),
+    /// The image has a precision that can not be kept
+    UnsupportedPrecision(String),
     ParseError(String),
-    General((Box<Error>, String)),
+    /// No tile fits at this position of the atlas
+    AtlasUnsatisfiable((u32, u32)),
+    /// Reading or writing the cache at the path failed
+    Cache((PathBuf, std::io::Error)),
+    /// Building the sample with this index failed
+    Sample((usize, Box<Error>)),
+    /// Building the tile with these corners (a, b, c, d) failed
+    Tile(([usize; 4], Box<Error>)),
+    /// The build was stopped with a `CancelToken`
+    Cancelled,
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Synthesis(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::ImageDecode((_, e)) => Some(e),
+            Self::Cache((_, e)) => Some(e),
+            Self::Sample((_, e)) => Some(e.as_ref()),
+            Self::Tile((_, e)) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
 
+/// The message of the variants wrapping another error only gives their context,
+/// the wrapped error is the `source`.
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::TsError(e) => write!(f, "{}", e),
+            Self::Synthesis(_) => write!(f, "Texture synthesis failed"),
             Self::Io(e) => write!(f, "{}", e),
+            Self::ImageDecode((path, _)) => write!(f, "Cannot read image {}", path.display()),
+            Self::InvalidInput(s) => write!(f, "Invalid input: {}", s),
             Self::SizeMismatch => write!(f, "Size mismatched"),
+            Self::EmptyImage => write!(f, "Image is empty"),
+            Self::OddSize((w, h)) => {
+                write!(f, "Image size ({}x{}) does not give tiles with even sides", w, h)
+            }
+            Self::UnsupportedPrecision(s) => {
+                write!(f, "Unsupported image precision: {}", s)
+            }
             Self::ParseError(s) => write!(f, "Parse error: {}", s),
-            Self::General((err, reason)) => write!(f, "{} {}", reason, err),
+            Self::AtlasUnsatisfiable((x, y)) => {
+                write!(f, "No tile fits the atlas at ({}, {})", x, y)
+            }
+            Self::Cache((path, _)) => write!(f, "Cache error at {}", path.display()),
+            Self::Sample((id, _)) => write!(f, "Fail to build sample {}", id),
+            Self::Tile(([a, b, c, d], _)) => write!(f, "Fail to build tile {}{}{}{}", a, b, c, d),
+            Self::Cancelled => write!(f, "Build was cancelled"),
         }
     }
 }
 
+impl Error {
+    /// Give the error the context of a sample, a cancellation is kept as is
+    pub(crate) fn in_sample(id: usize) -> impl FnOnce(Error) -> Error {
+        move |e| match e {
+            Error::Cancelled => e,
+            e => Error::Sample((id, Box::new(e))),
+        }
+    }
+
+    /// Give the error the context of a tile, a cancellation is kept as is
+    pub(crate) fn in_tile(corners: [usize; 4]) -> impl FnOnce(Error) -> Error {
+        move |e| match e {
+            Error::Cancelled => e,
+            e => Error::Tile((corners, Box::new(e))),
+        }
+    }
+
+    /// Give the error the context of a cache path
+    pub(crate) fn in_cache<P: Into<PathBuf>>(path: P) -> impl FnOnce(std::io::Error) -> Error {
+        let path = path.into();
+        move |e| Error::Cache((path, e))
+    }
+}
+
 impl From<ts::Error> for Error {
     fn from(error: ts::Error) -> Self {
-        Error::TsError(error)
+        Error::Synthesis(error)
     }
 }
 
@@ -0,0 +1,63 @@
+use crate::synthesis::SynthesisParams;
+use crate::{TileLayout, WTile};
+use std::time::Duration;
+
+/// Steps of a build, given to [`Report::event`](crate::Report::event)
+#[derive(Debug, Clone)]
+pub enum BuildEvent {
+    BuildStarted {
+        layout: TileLayout,
+        /// Names of the channels
+        channels: Vec<String>,
+        params: SynthesisParams,
+        /// Number of tiles of each channel
+        tiles: usize,
+    },
+    SampleStarted {
+        id: usize,
+    },
+    SampleFinished {
+        id: usize,
+        elapsed: Duration,
+    },
+    TileStarted {
+        id: usize,
+        /// Corner colors (a, b, c, d)
+        corners: [usize; 4],
+        /// Non zero when the tile is synthesized again
+        seed: u64,
+    },
+    TileFinished {
+        id: usize,
+        corners: [usize; 4],
+        seed: u64,
+        elapsed: Duration,
+    },
+    CacheHit {
+        key: String,
+    },
+    CacheMiss {
+        key: String,
+    },
+    BuildFinished {
+        tiles: usize,
+        /// Number of tiles synthesized again
+        retries: usize,
+        elapsed: Duration,
+    },
+}
+
+/// A tile built for each channel, given to
+/// [`Report::tile_finished`](crate::Report::tile_finished)
+#[derive(Clone)]
+pub struct FinishedTile {
+    /// Index of the tile in the set
+    pub id: usize,
+    /// Corner colors (a, b, c, d)
+    pub corners: [usize; 4],
+    /// The tile of each channel
+    pub tiles: Vec<WTile>,
+    /// Whether a retry gives the tile again, it replaces the tile given before
+    /// with the same id
+    pub replaced: bool,
+}
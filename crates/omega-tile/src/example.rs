@@ -0,0 +1,112 @@
+use crate::cache::hash_image;
+use crate::color::ColorOutput;
+use crate::error::Error;
+use crate::open_image;
+use std::path::Path;
+use texture_synthesis as ts;
+use ts::image::{imageops::FilterType, DynamicImage, GenericImageView};
+
+/// Which parts of an example texture-synthesis may copy patches from
+#[derive(Clone)]
+pub enum ExampleSampling {
+    All,
+    Ignore,
+    /// Only sample where the mask is white
+    Mask(DynamicImage),
+}
+
+impl std::fmt::Debug for ExampleSampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExampleSampling::All => write!(f, "All"),
+            ExampleSampling::Ignore => write!(f, "Ignore"),
+            ExampleSampling::Mask(img) => f.debug_tuple("Mask").field(&img.dimensions()).finish(),
+        }
+    }
+}
+
+impl ExampleSampling {
+    fn key(&self) -> String {
+        match self {
+            ExampleSampling::All => "all".to_string(),
+            ExampleSampling::Ignore => "ignore".to_string(),
+            ExampleSampling::Mask(img) => format!("mask+{}", hash_image(img)),
+        }
+    }
+}
+
+/// An additional image to copy patches from
+#[derive(Clone)]
+pub struct Reference {
+    pub img: DynamicImage,
+    pub sampling: ExampleSampling,
+}
+
+impl std::fmt::Debug for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reference")
+            .field("img", &self.img.dimensions())
+            .field("sampling", &self.sampling)
+            .finish()
+    }
+}
+
+impl Reference {
+    pub fn open<Q>(path: Q, sampling: ExampleSampling) -> Result<Reference, Error>
+    where
+        Q: AsRef<Path>,
+    {
+        Ok(Reference { img: open_image(path)?, sampling })
+    }
+
+    pub(crate) fn to_example<'a>(&self, color: ColorOutput) -> ts::ExampleBuilder<'a> {
+        let builder = ts::Example::builder(color.synthesis_image(&self.img));
+        match &self.sampling {
+            ExampleSampling::All => builder,
+            ExampleSampling::Ignore => builder.set_sample_method(ts::SampleMethod::Ignore),
+            ExampleSampling::Mask(mask) => {
+                let (w, h) = self.img.dimensions();
+                let mask = if mask.dimensions() != (w, h) {
+                    mask.resize_exact(w, h, FilterType::Nearest)
+                } else {
+                    mask.clone()
+                };
+                builder.set_sample_method(mask)
+            }
+        }
+    }
+}
+
+/// Examples used in addition to the four samples when synthesizing a tile
+#[derive(Debug, Clone, Default)]
+pub struct ExamplePool {
+    /// Add the whole input image as an example
+    pub input: Option<ExampleSampling>,
+    pub references: Vec<Reference>,
+}
+
+impl ExamplePool {
+    /// Key that identifies the pool in the cache
+    pub(crate) fn key(&self) -> String {
+        let mut key = match &self.input {
+            Some(sampling) => format!("input+{}", sampling.key()),
+            None => "none".to_string(),
+        };
+        for r in &self.references {
+            key.push_str(&format!("+{}+{}", hash_image(&r.img), r.sampling.key()));
+        }
+        key
+    }
+
+    /// Collect the examples of the pool, `input` is the image the samples come from
+    pub(crate) fn resolve(&self, input: Option<&DynamicImage>) -> Vec<Reference> {
+        let mut res = vec![];
+
+        if let (Some(sampling), Some(img)) = (&self.input, input) {
+            res.push(Reference { img: img.clone(), sampling: sampling.clone() });
+        }
+        res.extend(self.references.iter().cloned());
+
+        res
+    }
+}
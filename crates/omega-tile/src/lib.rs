@@ -6,22 +6,47 @@
 // Free texture for testing:
 // https://unsplash.com/
 
+mod analysis;
 mod atlas;
+mod builder;
 mod cache;
+mod cancel;
+mod channel;
+mod color;
+mod deep;
 mod error;
+mod event;
+mod example;
+mod mask;
+mod metrics;
+mod preprocess;
+mod seam;
+mod synthesis;
 mod wtile;
 
-use imageproc::drawing::draw_filled_circle_mut;
 use std::path::Path;
+use std::time::Instant;
 
-use wtile::WTile;
-
+pub use analysis::{analyze, analyze_image, Analysis};
 pub use atlas::{build_atlas, Atlas};
-pub use cache::Cache;
+pub use builder::{OmegaTile, OmegaTileBuilder, SampleSource};
+pub use cache::{Cache, CacheEntry, CacheStats, MemoryCache, PackedCache, TileCache};
+pub use cancel::CancelToken;
+pub use channel::{Channel, ChannelTiles, Channels, DeepTiles};
+pub use color::ColorOutput;
+pub use deep::{save_deep_image, Rgba16Image};
 pub use error::Error;
+pub use event::{BuildEvent, FinishedTile};
+pub use example::{ExamplePool, ExampleSampling, Reference};
+pub use mask::{Mask, MaskOptions, MaskShape};
+pub use metrics::{inspect, QualityReport, RetryPolicy, TileQuality, TileRetry};
+pub use preprocess::{Preprocess, TexelDensity};
+pub use seam::SeamOptions;
+pub use synthesis::{Quality, SynthesisParams};
 pub use texture_synthesis as ts;
+pub use wtile::{tiles_from_images, Direction, WTile};
 
-use ts::image::{DynamicImage, GenericImage, GenericImageView, Luma, Pixel, Rgba};
+use ts::image::{DynamicImage, GenericImage, GenericImageView, Luma};
 
 pub type WTileSet = Vec<WTile>;
 
@@ -39,6 +64,27 @@ impl ReportSection {
 
 pub trait Report {
     fn sub_progress_bar(&mut self, section: ReportSection) -> Box<dyn ts::GeneratorProgress>;
+
+    /// Called at each step of a build, does nothing by default
+    fn event(&mut self, _event: &BuildEvent) {}
+
+    /// Called as soon as a tile is built, before the whole set is done, and again
+    /// with `replaced` set when a retry replaces it. Does nothing by default
+    fn tile_finished(&mut self, _tile: &FinishedTile) {}
+}
+
+impl<R: Report + ?Sized> Report for Box<R> {
+    fn sub_progress_bar(&mut self, section: ReportSection) -> Box<dyn ts::GeneratorProgress> {
+        (**self).sub_progress_bar(section)
+    }
+
+    fn event(&mut self, event: &BuildEvent) {
+        (**self).event(event)
+    }
+
+    fn tile_finished(&mut self, tile: &FinishedTile) {
+        (**self).tile_finished(tile)
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -64,6 +110,62 @@ impl std::str::FromStr for WTileVariation {
     }
 }
 
+impl WTileVariation {
+    /// Number of tiles in a set
+    pub fn tile_count(self) -> usize {
+        match self {
+            WTileVariation::V4 => 4,
+            WTileVariation::V16 => 16,
+            WTileVariation::Full => 4 * 4 * 4 * 4,
+        }
+    }
+
+    /// Corner colors (a, b, c, d) of each tile of the set
+    pub fn corners(self) -> Vec<[usize; 4]> {
+        const R: usize = 0;
+        const G: usize = 1;
+        const B: usize = 2;
+        const Y: usize = 3;
+
+        match self {
+            // Figure 7(a)
+            WTileVariation::V4 => vec![[R, G, B, Y], [G, B, Y, R], [B, Y, R, G], [Y, R, G, B]],
+            // Figure 8(a)
+            WTileVariation::V16 => vec![
+                [R, G, G, B],
+                [R, B, G, Y],
+                [R, G, B, Y],
+                [R, B, B, R],
+                [G, B, B, Y],
+                [G, Y, B, R],
+                [G, B, Y, R],
+                [G, Y, Y, G],
+                [B, Y, Y, R],
+                [B, R, Y, G],
+                [B, Y, R, G],
+                [B, R, R, B],
+                [Y, R, R, G],
+                [Y, G, R, B],
+                [Y, R, G, B],
+                [Y, G, G, Y],
+            ],
+            WTileVariation::Full => {
+                let mut res = vec![];
+                for a in 0..4 {
+                    for b in 0..4 {
+                        for c in 0..4 {
+                            for d in 0..4 {
+                                res.push([a, b, c, d]);
+                            }
+                        }
+                    }
+                }
+                res
+            }
+        }
+    }
+}
+
 impl std::fmt::Display for WTileVariation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         let s = match self {
@@ -76,89 +178,375 @@ impl std::fmt::Display for WTileVariation {
     }
 }
 
+/// Tiles of a set, given by their corner colors
+#[derive(Debug, Clone)]
+pub enum TileLayout {
+    Variation(WTileVariation),
+    /// Corner colors (a, b, c, d) of each tile, colors are between 0 and 3
+    Custom(Vec<[usize; 4]>),
+}
+
+impl TileLayout {
+    pub fn corners(&self) -> Vec<[usize; 4]> {
+        match self {
+            TileLayout::Variation(it) => it.corners(),
+            TileLayout::Custom(it) => it.clone(),
+        }
+    }
+
+    pub fn tile_count(&self) -> usize {
+        match self {
+            TileLayout::Variation(it) => it.tile_count(),
+            TileLayout::Custom(it) => it.len(),
+        }
+    }
+}
+
+impl From<WTileVariation> for TileLayout {
+    fn from(variation: WTileVariation) -> Self {
+        TileLayout::Variation(variation)
+    }
+}
+
+impl std::fmt::Display for TileLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            TileLayout::Variation(it) => write!(f, "{}", it),
+            TileLayout::Custom(_) => write!(f, "custom"),
+        }
+    }
+}
+
+/// Settings shared by every tile of a build
+struct TileOptions {
+    mask: Mask,
+    /// The mask of every tile, if it does not depend on the tile content
+    fixed_mask: Option<DynamicImage>,
+    feather: bool,
+    /// Cache key prefix of the tiles
+    key: String,
+}
+
+/// Seed of the first generated sample, the next ones taking the following seeds.
+///
+/// The seed is part of the cache key of a sample.
+const SAMPLE_SEED_OFFSET: u64 = 1;
+
 struct WTileContext {
     pb: Box<dyn Report>,
-    cache: Option<Cache>,
+    cache: Option<Box<dyn TileCache>>,
+    params: SynthesisParams,
+    preprocess: Preprocess,
+    color: ColorOutput,
+    pool: ExamplePool,
+    /// Pool examples of each channel, resolved once the inputs are preprocessed
+    examples: Vec<Vec<Reference>>,
+    cancel: CancelToken,
+    /// Content hash of the input of each channel
+    input_keys: Vec<String>,
+    /// Added to the seed of every synthesis
+    seed: u64,
+    /// Whether the inputs are already cropped and resized, as 16 bits inputs are
+    /// before being split into bytes
+    resized: bool,
+    /// Number of channels given to the report, the low bytes of 16 bits inputs
+    /// are built as additional channels after them
+    channels: usize,
+}
+
+/// Open an 8 bits per channel image.
+///
+/// Deeper images are refused rather than quietly reduced to 8 bits. Only the
+/// inputs of a build may have 16 bits per channel, see [`OmegaTileBuilder::input`].
+pub fn open_image<Q>(path: Q) -> Result<DynamicImage, Error>
+where
+    Q: AsRef<Path>,
+{
+    use ts::image::ColorType::*;
+
+    let path = path.as_ref();
+    // Radiance HDR files would be tone mapped, and 16 bits PNGs stripped, while decoding
+    if let Ok(ts::image::ImageFormat::HDR) = ts::image::ImageFormat::from_path(path) {
+        return Err(Error::UnsupportedPrecision(format!("{} (32 bits float)", path.display())));
+    }
+    if deep::is_deep(path) {
+        return Err(Error::UnsupportedPrecision(format!("{} (16 bits)", path.display())));
+    }
+
+    ts::image::open(path).map_err(|e| match e {
+        ts::image::ImageError::UnsupportedColor(color) => match color {
+            Gray(bits) | GrayA(bits) | RGB(bits) | RGBA(bits) | BGR(bits) | BGRA(bits)
+                if bits > 8 =>
+            {
+                Error::UnsupportedPrecision(format!("{} ({} bits)", path.display(), bits))
+            }
+            _ => Error::ImageDecode((path.to_owned(), e)),
+        },
+        e => Error::ImageDecode((path.to_owned(), e)),
+    })
+}
+
+/// Open an image to measure it, a 16 bits per channel PNG is reduced to its high bytes.
+///
+/// Unlike [`open_image`], tile sets and samples saved with 16 bits can be opened,
+/// with enough precision to compare their tiles but not to build from them.
+pub fn open_reduced_image<Q>(path: Q) -> Result<DynamicImage, Error>
+where
+    Q: AsRef<Path>,
+{
+    let path = path.as_ref();
+    match deep::open_deep_image(path)? {
+        Some(img) => Ok(deep::split_bytes(&img).0),
+        None => open_image(path),
+    }
+}
+
+/// Reproduce a synthesis on the images of a channel, given in the order of the examples.
+///
+/// The transform wants one image per map up to the highest example it copied
+/// from, not one per example, so the count it asks for is learnt from its error.
+fn apply_transform(
+    transform: &ts::CoordinateTransform,
+    sources: Vec<DynamicImage>,
+) -> Result<DynamicImage, Error> {
+    let img = match transform.apply(sources.iter().cloned()) {
+        Err(ts::Error::MapsCountMismatch(_, count)) => {
+            transform.apply(fit_maps(sources, count as usize))?
+        }
+        res => res?,
+    };
+    Ok(DynamicImage::ImageRgba8(img))
+}
+
+/// `sources` trimmed or padded to `count` maps, map `i` being the source of example `i`.
+///
+/// Trimmed examples were never copied from, and padded maps are never read.
+fn fit_maps(mut sources: Vec<DynamicImage>, count: usize) -> Vec<DynamicImage> {
+    sources.resize(count, DynamicImage::new_rgba8(1, 1));
+    sources
 }
 
 impl WTileContext {
-    fn build_samples<Q>(&mut self, mode: SampleMode, path: Q) -> Result<Vec<DynamicImage>, Error>
-    where
-        Q: AsRef<Path>,
-    {
+    /// Cache key prefix identifying the library version and the primary input
+    fn base_key(&self) -> String {
+        format!("{}+{}", cache::key_prefix(), self.input_keys[0])
+    }
+
+    /// Suffix appended to the cache keys of a channel
+    fn channel_key(&self, index: usize) -> String {
+        if index == 0 {
+            String::new()
+        } else {
+            format!("+{}", self.input_keys[index])
+        }
+    }
+
+    fn emit(&mut self, event: BuildEvent) {
+        self.pb.event(&event);
+    }
+
+    fn read_cache_all(&mut self, keys: &[String]) -> Option<Vec<DynamicImage>> {
+        let cache = self.cache.as_mut()?;
+        let res: Option<Vec<_>> = keys.iter().map(|key| cache.read(key)).collect();
+
+        let key = keys.first().cloned().unwrap_or_default();
+        self.emit(match res {
+            Some(_) => BuildEvent::CacheHit { key },
+            None => BuildEvent::CacheMiss { key },
+        });
+        res
+    }
+
+    fn write_cache_all(&mut self, keys: &[String], imgs: &[DynamicImage]) -> Result<(), Error> {
+        if let Some(cache) = self.cache.as_mut() {
+            for (key, img) in keys.iter().zip(imgs) {
+                cache.write(key, img)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_cancelled(&self) -> Result<(), Error> {
+        if self.cancel.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Run a synthesis, which is abandoned if the build is cancelled meanwhile
+    fn run(
+        &mut self,
+        session: ts::Session,
+        section: ReportSection,
+    ) -> Result<ts::GeneratedImage, Error> {
+        self.check_cancelled()?;
+        cancel::run_session(session, self.pb.sub_progress_bar(section), &self.cancel)
+    }
+
+    /// Images of every channel from a synthesis driven by the primary channel.
+    ///
+    /// `sources` holds the images of each channel in the same order as the
+    /// examples. With alpha the primary channel is rebuilt from its sources too,
+    /// since texture-synthesis only saw premultiplied colors.
+    fn transfer(
+        &self,
+        generated: ts::GeneratedImage,
+        sources: Vec<Vec<DynamicImage>>,
+    ) -> Result<Vec<DynamicImage>, Error> {
+        let transform = generated.get_coordinate_transform();
+        let mut sources = sources.into_iter();
+        let first = sources.next().unwrap_or_default();
+        let mut imgs = match self.color {
+            ColorOutput::Rgb => vec![generated.into_image()],
+            ColorOutput::Rgba => vec![apply_transform(&transform, first)?],
+        };
+        for it in sources {
+            imgs.push(apply_transform(&transform, it)?);
+        }
+        Ok(imgs)
+    }
+
+    /// Build the 4 samples of each channel
+    fn build_samples(
+        &mut self,
+        mode: SampleMode,
+        inputs: Vec<DynamicImage>,
+    ) -> Result<Vec<Vec<DynamicImage>>, Error> {
+        if inputs.iter().any(|it| it.dimensions() != inputs[0].dimensions()) {
+            return Err(Error::SizeMismatch);
+        }
+
+        let tiles_per_side = mode.tiles_per_side();
+        let inputs = self.preprocess_inputs(inputs, tiles_per_side)?;
+        let dim = inputs[0].dimensions();
+        preprocess::check_splitable(dim, tiles_per_side)?;
+
+        let pool = &self.pool;
+        self.examples =
+            inputs.iter().map(|img| pool.resolve(pool.input.as_ref().map(|_| img))).collect();
+
         match mode {
             SampleMode::Generate => {
-                let dim = {
-                    let img = ts::image::open(&path).map_err(|_| {
-                        std::io::Error::new(std::io::ErrorKind::NotFound, "Not Found")
-                    })?;
-                    img.dimensions()
-                };
+                let base = self.base_key();
 
-                let mut build_sample = |id, progress: (usize, usize)| -> Result<_, Error> {
-                    let key = format!(
-                        "{}+{}+{}+{}+samples",
+                let mut build_sample = |seed: u64, progress: (usize, usize)| -> Result<_, Error> {
+                    self.emit(BuildEvent::SampleStarted { id: progress.0 });
+                    let start = Instant::now();
+
+                    let mut key = format!(
+                        "{}+{}+{}+{}+{}+{}+samples",
                         dim.0,
                         dim.1,
-                        &path.as_ref().to_string_lossy(),
-                        id
+                        base,
+                        self.preprocess.key(),
+                        self.params.key(),
+                        seed
                     );
-                    if let Some(img) = self.cache.as_mut().and_then(|it| it.read_cache(&key)) {
-                        Ok(img)
+                    if self.color == ColorOutput::Rgba {
+                        key.push_str("+rgba");
+                    }
+                    if self.seed != 0 {
+                        key.push_str(&format!("+seed{}", self.seed));
+                    }
+                    let keys: Vec<_> = (0..inputs.len())
+                        .map(|c| format!("{}{}", key, self.channel_key(c)))
+                        .collect();
+
+                    let imgs = if let Some(imgs) = self.read_cache_all(&keys) {
+                        imgs
                     } else {
-                        let texsynth = ts::Session::builder()
-                            .add_example(&path)
+                        let texsynth = self
+                            .params
+                            .apply(ts::Session::builder())
+                            .add_example(self.color.synthesis_image(&inputs[0]))
                             .output_size(ts::Dims::new(dim.0, dim.1))
-                            .seed(id)
+                            .seed(self.seed.wrapping_add(seed))
                             .build()?;
                         let report_section = ReportSection::new("build sample", progress);
-                        let generated =
-                            texsynth.run(Some(self.pb.sub_progress_bar(report_section)));
-                        let img = generated.into_image();
-                        if let Some(cache) = self.cache.as_mut() {
-                            cache.write_cache(&key, &img)?;
-                        }
-                        Ok(img)
-                    }
+                        let generated = self.run(texsynth, report_section)?;
+
+                        // Reproduce the same patch placement in the other channels
+                        let sources = inputs.iter().map(|it| vec![it.clone()]).collect();
+                        let imgs = self.transfer(generated, sources)?;
+
+                        self.write_cache_all(&keys, &imgs)?;
+                        imgs
+                    };
+
+                    self.emit(BuildEvent::SampleFinished {
+                        id: progress.0,
+                        elapsed: start.elapsed(),
+                    });
+                    Ok(imgs)
                 };
 
-                let mut result = vec![];
-                for i in 0..4 {
-                    result.push(build_sample(i - 1, (i as usize, 4 as usize))?);
+                let mut result = vec![vec![]; inputs.len()];
+                for i in 0..4u64 {
+                    let imgs = build_sample(i + SAMPLE_SEED_OFFSET, (i as usize, 4))
+                        .map_err(Error::in_sample(i as usize))?;
+                    for (samples, img) in result.iter_mut().zip(imgs) {
+                        samples.push(img);
+                    }
                 }
                 Ok(result)
             }
             SampleMode::Split => {
-                let img = ts::image::open(&path)
-                    .map_err(|_| std::io::Error::new(std::io::ErrorKind::NotFound, "Not Found"))?;
-
-                // Check if its squared
-                let dims = img.dimensions();
-                if !is_splitable(dims) {
-                    std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "Give texture size is invalide. (Must be square and even",
-                    );
+                return Ok(inputs.iter().map(|img| split(img, (dim.0 / 2, dim.1 / 2))).collect());
+
+                fn split(img: &DynamicImage, (w2, h2): (u32, u32)) -> Vec<DynamicImage> {
+                    vec![
+                        DynamicImage::ImageRgba8(img.view(0, 0, w2, h2).to_image()),
+                        DynamicImage::ImageRgba8(img.view(0, h2, w2, h2).to_image()),
+                        DynamicImage::ImageRgba8(img.view(w2, 0, w2, h2).to_image()),
+                        DynamicImage::ImageRgba8(img.view(w2, h2, w2, h2).to_image()),
+                    ]
                 }
+            }
+        }
+    }
 
-                let half = dims.0 / 2;
-                let mut result: Vec<DynamicImage> = vec![];
+    /// Crop, resize and heal the input of each channel
+    fn preprocess_inputs(
+        &mut self,
+        inputs: Vec<DynamicImage>,
+        tiles_per_side: u32,
+    ) -> Result<Vec<DynamicImage>, Error> {
+        let inputs = if self.resized {
+            inputs
+        } else {
+            inputs
+                .iter()
+                .map(|it| self.preprocess.resize(it, tiles_per_side).map(DynamicImage::ImageRgba8))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        if !self.preprocess.seamless {
+            return Ok(inputs);
+        }
 
-                result.push(DynamicImage::ImageRgba8(img.view(0, 0, half, half).to_image()));
-                result.push(DynamicImage::ImageRgba8(img.view(0, half, half, half).to_image()));
-                result.push(DynamicImage::ImageRgba8(img.view(half, 0, half, half).to_image()));
-                result.push(DynamicImage::ImageRgba8(img.view(half, half, half, half).to_image()));
+        // Move the borders to the center and synthesize them again
+        let offset: Vec<_> = inputs.iter().map(preprocess::offset_half).collect();
+        let dim = offset[0].dimensions();
+        let mask = preprocess::heal_mask(dim);
 
-                return Ok(result);
+        let texsynth = self
+            .params
+            .apply(ts::Session::builder())
+            .inpaint_example(
+                mask.clone(),
+                ts::Example::builder(self.color.synthesis_image(&offset[0]))
+                    .set_sample_method(mask),
+                ts::Dims::new(dim.0, dim.1),
+            )
+            .build()?;
+        let report_section = ReportSection::new("make seamless", (0, 1));
+        let generated = self.run(texsynth, report_section)?;
 
-                fn is_splitable(dims: (u32, u32)) -> bool {
-                    if dims.0 == 0 || dims.0 != dims.1 {
-                        return false;
-                    }
-                    dims.0 % 2 == 0
-                }
-            }
-        }
+        // Reproduce the same patch placement in the other channels
+        let sources = offset.iter().map(|it| vec![it.clone()]).collect();
+        let healed = self.transfer(generated, sources)?;
+
+        Ok(healed.iter().map(preprocess::offset_back).collect())
     }
 
     fn merge_samples(
@@ -171,7 +559,7 @@ impl WTileContext {
     ) -> Result<DynamicImage, Error> {
         let (w, h) = imgs[0].dimensions();
         let (w2, h2) = (w / 2, h / 2);
-        let mut res = DynamicImage::new_rgb8(w, h);
+        let mut res = DynamicImage::new_rgba8(w, h);
 
         // *-----------*
         // |  A  |  B  |
@@ -202,267 +590,510 @@ impl WTileContext {
         Ok(res)
     }
 
-    /// Build a cross star like mask
-    fn build_mask(&self, (w, h): (u32, u32)) -> Result<DynamicImage, Error> {
-        let mut res = DynamicImage::new_rgb8(w, h);
-        for i in 0..w {
-            for j in 0..h {
-                res.put_pixel(i, j, Rgba([0, 0, 0, 0]));
-            }
-        }
-
-        let (w, h) = (w as i32, h as i32);
-
-        draw_filled_circle_mut(&mut res, (0, 0), w / 2, Rgba([255u8, 255u8, 255u8, 255u8]));
-        draw_filled_circle_mut(&mut res, (w, 0), w / 2, Rgba([255u8, 255u8, 255u8, 255u8]));
-        draw_filled_circle_mut(&mut res, (0, h), w / 2, Rgba([255u8, 255u8, 255u8, 255u8]));
-        draw_filled_circle_mut(&mut res, (w, h), w / 2, Rgba([255u8, 255u8, 255u8, 255u8]));
-
-        Ok(res)
+    /// Build the mask of a tile made of the samples `corners` (a, b, c, d), see [`Mask::build`].
+    ///
+    /// Shape and image masks are the same for every tile, a seam mask is cut through
+    /// the samples of each tile.
+    fn build_mask(&self, mask: &Mask, corners: [&DynamicImage; 4]) -> Result<DynamicImage, Error> {
+        mask.build(corners)
     }
 
+    /// Synthesize the tile of each channel, `merged` and `samples` are indexed by channel
     fn build_tile(
         &mut self,
-        merged: &DynamicImage,
-        mask: &DynamicImage,
-        samples: &[DynamicImage],
+        merged: &[DynamicImage],
+        soft_mask: &DynamicImage,
+        feather: bool,
+        samples: &[Vec<DynamicImage>],
+        seed: u64,
         progress: (usize, usize),
-    ) -> Result<DynamicImage, Error> {
-        let output_dim = mask.dimensions();
-
-        let examples: Vec<_> = samples.iter().map(|it| it.clone()).collect();
-
-        let texsynth = ts::Session::builder()
-            .add_examples(examples.into_iter())
+    ) -> Result<Vec<DynamicImage>, Error> {
+        let output_dim = soft_mask.dimensions();
+        let mask = mask::binarize(soft_mask);
+
+        let examples: Vec<_> = samples[0]
+            .iter()
+            .map(|it| ts::Example::builder(self.color.synthesis_image(it)))
+            .chain(self.examples[0].iter().map(|it| it.to_example(self.color)))
+            .collect();
+
+        let texsynth = self
+            .params
+            .apply(ts::Session::builder())
+            .add_examples(examples)
             .inpaint_example(
                 mask.clone(),
                 // This will prevent sampling from the imgs/2.jpg, note that
                 // we *MUST* provide at least one example to source from!
-                ts::Example::builder(merged.clone())
+                ts::Example::builder(self.color.synthesis_image(&merged[0]))
                     //  .set_sample_method(ts::SampleMethod::Ignore),
                     .set_sample_method(mask.clone()),
                 ts::Dims::new(output_dim.0, output_dim.1),
             )
+            .seed(seed)
             .build()?;
 
         let report_section = ReportSection::new("build tile", progress);
-        let generated = texsynth.run(Some(self.pb.sub_progress_bar(report_section)));
-
-        Ok(generated.into_image())
+        let generated = self.run(texsynth, report_section)?;
+
+        // Reproduce the same patch placement in the other channels, the sources
+        // must be in the same order as the examples above.
+        let sources = (0..merged.len())
+            .map(|c| {
+                samples[c]
+                    .iter()
+                    .cloned()
+                    .chain(self.examples[c].iter().map(|it| it.img.clone()))
+                    .chain(std::iter::once(merged[c].clone()))
+                    .collect()
+            })
+            .collect();
+        let imgs = self.transfer(generated, sources)?;
+
+        if feather {
+            Ok(imgs.iter().zip(merged).map(|(img, m)| mask::blend(img, m, soft_mask)).collect())
+        } else {
+            Ok(imgs)
+        }
     }
 
     fn build_n_w_tiles_with_generator<F>(
-        n_tiles: WTileVariation,
+        layout: &TileLayout,
         mut gen: F,
-    ) -> Result<Vec<WTile>, Error>
+    ) -> Result<Vec<WTileSet>, Error>
     where
-        F: FnMut(usize, usize, usize, usize, (usize, usize)) -> Result<DynamicImage, Error>,
+        F: FnMut(usize, usize, usize, usize, (usize, usize)) -> Result<Vec<WTile>, Error>,
     {
-        macro_rules! rgby {
-            (R) => {
-                0
-            };
-            (G) => {
-                1
-            };
-            (B) => {
-                2
-            };
-            (Y) => {
-                3
-            };
+        let corners = layout.corners();
+
+        // One tile set for each channel
+        let mut res: Vec<WTileSet> = vec![];
+        for (n, &[a, b, c, d]) in corners.iter().enumerate() {
+            let tiles = gen(a, b, c, d, (n, corners.len()))?;
+            if res.is_empty() {
+                res.resize(tiles.len(), vec![]);
+            }
+            for (set, tile) in res.iter_mut().zip(tiles) {
+                set.push(tile);
+            }
         }
 
-        let mut res = vec![];
+        Ok(res)
+    }
 
-        macro_rules! make_tile {
-            ($a:ident, $b:ident, $c:ident, $d:ident, $total:expr) => {
-                let img = gen(rgby!($a), rgby!($b), rgby!($c), rgby!($d), (res.len(), $total))?;
-                res.push(WTile::new(img, rgby!($a), rgby!($b), rgby!($c), rgby!($d)));
-            };
+    /// Make the tiles of each channel and hand them to the report
+    fn finish_tile(
+        &mut self,
+        id: usize,
+        corners: [usize; 4],
+        imgs: Vec<DynamicImage>,
+    ) -> Vec<WTile> {
+        let [a, b, c, d] = corners;
+        let tiles: Vec<_> = imgs.into_iter().map(|img| WTile::new(img, a, b, c, d)).collect();
+        let shown = tiles[..self.channels.min(tiles.len())].to_vec();
+        self.pb.tile_finished(&FinishedTile { id, corners, tiles: shown, replaced: false });
+        tiles
+    }
+
+    /// Settings shared by every tile of a build
+    fn tile_options(
+        &self,
+        layout: &TileLayout,
+        mode: SampleMode,
+        samples: &[Vec<DynamicImage>],
+        mask: &Mask,
+        pool_key: &str,
+    ) -> Result<TileOptions, Error> {
+        let base = self.base_key();
+        let mut options_key =
+            format!("{}+{}+{}+{}", self.preprocess.key(), mask.key(), self.params.key(), pool_key);
+        if self.color == ColorOutput::Rgba {
+            // Keep the keys of RGB builds unchanged
+            options_key.push_str("+rgba");
         }
+        let fixed_mask = if mask.is_per_tile() {
+            None
+        } else {
+            // Fixed masks only depend on the tile dimensions
+            Some(self.build_mask(mask, [&samples[0][0]; 4])?)
+        };
 
-        match n_tiles {
-            // 4
-            WTileVariation::V4 => {
-                let count = 4;
-                // Figure 7(a)
-                make_tile!(R, G, B, Y, count);
-                make_tile!(G, B, Y, R, count);
-                make_tile!(B, Y, R, G, count);
-                make_tile!(Y, R, G, B, count);
-            }
-            WTileVariation::V16 => {
-                let count = 16;
-
-                // // Figure 8(b)
-                // make_tile!(R, G, G, Y);
-                // make_tile!(R, B, G, R);
-                // make_tile!(R, B, B, Y);
-                // make_tile!(R, G, B, R);
-
-                // make_tile!(Y, G, G, R);
-                // make_tile!(Y, B, G, Y);
-                // make_tile!(Y, G, B, Y);
-                // make_tile!(Y, B, B, R);
-
-                // make_tile!(B, R, R, B);
-                // make_tile!(B, Y, R, G);
-                // make_tile!(B, R, Y, G);
-                // make_tile!(B, Y, Y, B);
-
-                // make_tile!(G, R, R, G);
-                // make_tile!(G, Y, R, B);
-                // make_tile!(G, R, Y, B);
-                // make_tile!(G, Y, Y, G);
-
-                // Figure 8(a)
-                make_tile!(R, G, G, B, count);
-                make_tile!(R, B, G, Y, count);
-                make_tile!(R, G, B, Y, count);
-                make_tile!(R, B, B, R, count);
-
-                make_tile!(G, B, B, Y, count);
-                make_tile!(G, Y, B, R, count);
-                make_tile!(G, B, Y, R, count);
-                make_tile!(G, Y, Y, G, count);
-
-                make_tile!(B, Y, Y, R, count);
-                make_tile!(B, R, Y, G, count);
-                make_tile!(B, Y, R, G, count);
-                make_tile!(B, R, R, B, count);
-
-                make_tile!(Y, R, R, G, count);
-                make_tile!(Y, G, R, B, count);
-                make_tile!(Y, R, G, B, count);
-                make_tile!(Y, G, G, Y, count);
-            }
+        Ok(TileOptions {
+            mask: mask.clone(),
+            fixed_mask,
+            feather: mask.feather() > 0,
+            key: format!("{}+{}+{}+{}", layout, mode.key(), base, options_key),
+        })
+    }
 
-            WTileVariation::Full => {
-                for a in 0..4 {
-                    for b in 0..4 {
-                        for c in 0..4 {
-                            for d in 0..4 {
-                                let img = gen(a, b, c, d, (res.len(), 4 * 4 * 4 * 4))?;
-                                res.push(WTile::new(img, a, b, c, d));
-                            }
-                        }
-                    }
-                }
-            }
+    /// Build the tile of each channel for the given corners
+    fn synthesize_tile(
+        &mut self,
+        opts: &TileOptions,
+        samples: &[Vec<DynamicImage>],
+        id: usize,
+        (a, b, c, d): (usize, usize, usize, usize),
+        seed: u64,
+        progress: (usize, usize),
+    ) -> Result<Vec<DynamicImage>, Error> {
+        self.check_cancelled()?;
+
+        let corners = [a, b, c, d];
+        self.emit(BuildEvent::TileStarted { id, corners, seed });
+        let start = Instant::now();
+
+        let mut key = format!("{}+{}+{}+{}+{}", opts.key, a, b, c, d);
+        let synthesis_seed = self.seed.wrapping_add(seed);
+        if synthesis_seed != 0 {
+            key.push_str(&format!("+seed{}", synthesis_seed));
         }
+        let keys: Vec<_> =
+            (0..samples.len()).map(|i| format!("{}{}", key, self.channel_key(i))).collect();
+
+        let imgs = if let Some(imgs) = self.read_cache_all(&keys) {
+            imgs
+        } else {
+            let imgs = (|| -> Result<_, Error> {
+                let merged = samples
+                    .iter()
+                    .map(|it| self.merge_samples(it, a, b, c, d))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let tile_mask = match &opts.fixed_mask {
+                    Some(it) => it.clone(),
+                    None => {
+                        let s = &samples[0];
+                        self.build_mask(&opts.mask, [&s[a], &s[b], &s[c], &s[d]])?
+                    }
+                };
+                self.build_tile(
+                    &merged,
+                    &tile_mask,
+                    opts.feather,
+                    samples,
+                    synthesis_seed,
+                    progress,
+                )
+            })()
+            .map_err(Error::in_tile(corners))?;
+            self.write_cache_all(&keys, &imgs)?;
+            imgs
+        };
 
-        Ok(res)
+        self.emit(BuildEvent::TileFinished { id, corners, seed, elapsed: start.elapsed() });
+        Ok(imgs.iter().map(|it| self.color.convert(it)).collect())
     }
 
-    fn build_n_w_tiles<Q>(
+    fn build_n_w_tiles(
         &mut self,
-        n_tiles: WTileVariation,
-        samples: &[DynamicImage],
-        mask: &DynamicImage,
-        base: Q,
-    ) -> Result<Vec<WTile>, Error>
-    where
-        Q: AsRef<Path> + std::fmt::Display,
-    {
-        Self::build_n_w_tiles_with_generator(n_tiles, |a, b, c, d, progress: (usize, usize)| {
-            let key = format!("{}+{}+{}+{}+{}+{}", n_tiles, base, a, b, c, d);
-            let img = if let Some(img) = self.cache.as_mut().and_then(|it| it.read_cache(&key)) {
-                img
-            } else {
-                let merged = self.merge_samples(&samples, a, b, c, d)?;
-                let img = self.build_tile(&merged, &mask, &samples, progress)?;
-                if let Some(cache) = self.cache.as_mut() {
-                    cache.write_cache(&key, &img)?;
+        layout: &TileLayout,
+        samples: &[Vec<DynamicImage>],
+        opts: &TileOptions,
+    ) -> Result<Vec<WTileSet>, Error> {
+        Self::build_n_w_tiles_with_generator(layout, |a, b, c, d, progress: (usize, usize)| {
+            let id = progress.0;
+            let imgs = self.synthesize_tile(opts, samples, id, (a, b, c, d), 0, progress)?;
+            Ok(self.finish_tile(id, [a, b, c, d], imgs))
+        })
+    }
+
+    /// Synthesize again, with other seeds, the tiles scoring worse than the policy
+    /// threshold, keeping the best attempt.
+    fn retry_tiles(
+        &mut self,
+        policy: &RetryPolicy,
+        tiles: &mut [WTileSet],
+        samples: &[Vec<DynamicImage>],
+        opts: &TileOptions,
+    ) -> Result<Vec<TileRetry>, Error> {
+        let report = metrics::inspect(&tiles[0], Some(&samples[0][..]))?;
+        let failed: Vec<_> =
+            report.tiles.iter().filter(|it| it.score() > policy.threshold).collect();
+
+        let mut retries = vec![];
+        for (n, quality) in failed.iter().enumerate() {
+            let id = quality.id;
+            let [a, b, c, d] = tiles[0][id].corners();
+            let initial = tiles.iter().map(|it| it[id].clone()).collect::<Vec<_>>();
+
+            let mut best = (quality.score(), initial);
+            let mut attempts = 0;
+            for seed in 1..=u64::from(policy.attempts) {
+                attempts += 1;
+                let imgs =
+                    self.synthesize_tile(opts, samples, id, (a, b, c, d), seed, (n, failed.len()))?;
+                let candidate: Vec<_> =
+                    imgs.into_iter().map(|img| WTile::new(img, a, b, c, d)).collect();
+
+                tiles[0][id] = candidate[0].clone();
+                let score = metrics::inspect_tile(&tiles[0], Some(&samples[0][..]), id)?.score();
+                if score < best.0 {
+                    best = (score, candidate);
                 }
-                img
-            };
+                if best.0 <= policy.threshold {
+                    break;
+                }
+            }
 
-            Ok(img)
-        })
+            for (set, tile) in tiles.iter_mut().zip(best.1.iter().cloned()) {
+                set[id] = tile;
+            }
+            // The tile kept is reported again, even if it is the initial one
+            let mut shown = best.1;
+            shown.truncate(self.channels);
+            self.pb.tile_finished(&FinishedTile {
+                id,
+                corners: [a, b, c, d],
+                tiles: shown,
+                replaced: true,
+            });
+            retries.push(TileRetry { id, attempts, initial_score: quality.score(), score: best.0 });
+        }
+
+        Ok(retries)
     }
 
     fn build_test_tiles(
         &mut self,
-        n_tiles: WTileVariation,
+        layout: &TileLayout,
         samples: &[DynamicImage],
     ) -> Result<Vec<WTile>, Error> {
-        Self::build_n_w_tiles_with_generator(n_tiles, |a, b, c, d, _| {
-            let key = format!("{}+{}+{}+{}+{}+{}", n_tiles, "test", a, b, c, d);
-            let img = if let Some(img) = self.cache.as_mut().and_then(|it| it.read_cache(&key)) {
+        let sets = Self::build_n_w_tiles_with_generator(layout, |a, b, c, d, progress| {
+            self.check_cancelled()?;
+            let key =
+                format!("{}+{}+{}+{}+{}+{}+{}", cache::key_prefix(), layout, "test", a, b, c, d);
+            let img = if let Some(img) = self.cache.as_mut().and_then(|it| it.read(&key)) {
                 img
             } else {
-                let img = self.merge_samples(&samples, a, b, c, d)?;
+                let img = self.merge_samples(samples, a, b, c, d)?;
                 if let Some(cache) = self.cache.as_mut() {
-                    cache.write_cache(&key, &img)?;
+                    cache.write(&key, &img)?;
                 }
                 img
             };
 
-            Ok(img)
-        })
+            Ok(self.finish_tile(progress.0, [a, b, c, d], vec![img]))
+        })?;
+
+        Ok(sets.into_iter().next().unwrap_or_default())
     }
 }
 
+#[derive(Debug, Copy, Clone)]
 pub enum SampleMode {
     Generate,
     Split,
 }
 
-pub fn build(
-    mode: SampleMode,
-    base: &str,
-    variation: WTileVariation,
-    report: impl Report + 'static,
-    cache: Option<Cache>,
-) -> Result<(WTileSet, Vec<DynamicImage>), Error> {
-    let mut ctx = WTileContext { pb: Box::new(report), cache };
-
-    let samples = ctx
-        .build_samples(mode, &base)
-        .map_err(|e| Error::General((Box::new(e), "Fail to build samples".to_string())))?;
+impl SampleMode {
+    fn key(self) -> &'static str {
+        match self {
+            SampleMode::Generate => "generate",
+            SampleMode::Split => "split",
+        }
+    }
 
-    let mask = ctx.build_mask(samples[0].dimensions())?;
+    /// Number of tiles the input is split into along each axis
+    fn tiles_per_side(self) -> u32 {
+        match self {
+            SampleMode::Generate => 1,
+            SampleMode::Split => 2,
+        }
+    }
+}
 
-    Ok((ctx.build_n_w_tiles(variation, &samples, &mask, &base)?, samples))
+/// Tiles built from an input, with one ω-tile set for each channel
+pub struct BuildResult {
+    pub channels: Vec<ChannelTiles>,
+    /// Tiles which were synthesized again because of their quality score
+    pub retries: Vec<TileRetry>,
 }
 
-pub fn build_testset(
-    variation: WTileVariation,
-    report: impl Report + 'static,
-    cache: Option<Cache>,
-) -> Result<WTileSet, Error> {
-    let mut ctx = WTileContext { pb: Box::new(report), cache };
-
-    let samples = {
-        let mut samples: Vec<DynamicImage> = Vec::new();
-
-        fn fill(img: &mut DynamicImage, color: Rgba<u8>) {
-            let dim = img.dimensions();
-            for y in 0..dim.1 {
-                for x in 0..dim.0 {
-                    img.put_pixel(x, y, color);
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ts::image::{Rgba, RgbaImage};
+
+    struct NoReport;
+
+    struct NoProgress;
+
+    impl ts::GeneratorProgress for NoProgress {
+        fn update(&mut self, _info: ts::ProgressUpdate<'_>) {}
+    }
+
+    impl Report for NoReport {
+        fn sub_progress_bar(&mut self, _section: ReportSection) -> Box<dyn ts::GeneratorProgress> {
+            Box::new(NoProgress)
+        }
+    }
+
+    fn context() -> WTileContext {
+        WTileContext {
+            pb: Box::new(NoReport),
+            cache: None,
+            params: SynthesisParams::default(),
+            preprocess: Preprocess::default(),
+            color: ColorOutput::Rgba,
+            pool: ExamplePool::default(),
+            examples: vec![vec![]],
+            cancel: CancelToken::default(),
+            input_keys: vec![String::new()],
+            seed: 0,
+            resized: false,
+            channels: 1,
+        }
+    }
+
+    /// Opaque image where every pixel encodes its coordinates
+    fn gradient((w, h): (u32, u32)) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(w, h, |x, y| Rgba([x as u8, y as u8, 0, 255])))
+    }
+
+    #[test]
+    fn split_rectangular_input() {
+        let mut ctx = context();
+        let samples = ctx.build_samples(SampleMode::Split, vec![gradient((48, 24))]).unwrap();
+
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].len(), 4);
+        for it in &samples[0] {
+            assert_eq!(it.dimensions(), (24, 12));
+        }
+
+        let merged = ctx.merge_samples(&samples[0], 0, 1, 2, 3).unwrap();
+        assert_eq!(merged.dimensions(), (24, 12));
+        // Every pixel comes from a sample, so none is left transparent
+        assert!(merged.to_rgba().pixels().all(|p| p[3] == 255));
+        // The top left quadrant is the bottom right quarter of the first sample
+        assert_eq!(merged.get_pixel(0, 0), samples[0][0].get_pixel(12, 6));
+    }
+
+    #[test]
+    fn split_refuses_odd_tiles() {
+        let mut ctx = context();
+        match ctx.build_samples(SampleMode::Split, vec![gradient((42, 24))]) {
+            Err(Error::OddSize((42, 24))) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("tiles of 21x12 can not be split into 4 quadrants"),
+        }
+    }
+
+    #[test]
+    fn pool_input_is_preprocessed() {
+        let mut ctx = context();
+        ctx.pool.input = Some(ExampleSampling::All);
+        ctx.preprocess.square = true;
+        ctx.build_samples(SampleMode::Split, vec![gradient((48, 32))]).unwrap();
+
+        // The whole input is an example as cropped, not as opened
+        assert_eq!(ctx.examples.len(), 1);
+        assert_eq!(ctx.examples[0][0].img.dimensions(), (32, 32));
+    }
+
+    #[test]
+    fn generate_samples() {
+        let mut ctx = context();
+        ctx.params = SynthesisParams::preset(Quality::Fast);
+        let input = DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, y| {
+            Rgba([(x * 37 % 256) as u8, (y * 53 % 256) as u8, ((x ^ y) * 7 % 256) as u8, 255])
+        }));
+        let samples = ctx.build_samples(SampleMode::Generate, vec![input]).unwrap();
+
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].len(), 4);
+        for (i, sample) in samples[0].iter().enumerate() {
+            assert_eq!(sample.dimensions(), (32, 32));
+            // Each sample has its own seed
+            for other in &samples[0][..i] {
+                assert!(!sample.raw_pixels().eq(&other.raw_pixels()));
             }
         }
+    }
 
-        let mut r_img = DynamicImage::new_rgb8(128, 128);
-        let mut g_img = DynamicImage::new_rgb8(128, 128);
-        let mut b_img = DynamicImage::new_rgb8(128, 128);
-        let mut y_img = DynamicImage::new_rgb8(128, 128);
+    #[test]
+    fn fit_maps_to_transform() {
+        let sources = vec![gradient((4, 4)), gradient((8, 8)), gradient((2, 2))];
+        let trimmed = fit_maps(sources.clone(), 2);
+        assert_eq!(trimmed.len(), 2);
+        assert_eq!(trimmed[1].dimensions(), (8, 8));
+
+        let padded = fit_maps(sources, 5);
+        assert_eq!(padded.len(), 5);
+        assert_eq!(padded[0].dimensions(), (4, 4));
+        assert_eq!(padded[2].dimensions(), (2, 2));
+    }
 
-        fill(&mut r_img, Rgba::from_channels(255, 0, 0, 255));
-        fill(&mut g_img, Rgba::from_channels(0, 255, 0, 255));
-        fill(&mut b_img, Rgba::from_channels(0, 0, 255, 255));
-        fill(&mut y_img, Rgba::from_channels(128, 128, 128, 255));
+    /// Report keeping the id and the `replaced` flag of the finished tiles
+    struct FinishedTiles(std::rc::Rc<std::cell::RefCell<Vec<(usize, bool)>>>);
 
-        samples.push(r_img);
-        samples.push(g_img);
-        samples.push(b_img);
-        samples.push(y_img);
+    impl Report for FinishedTiles {
+        fn sub_progress_bar(&mut self, _section: ReportSection) -> Box<dyn ts::GeneratorProgress> {
+            Box::new(NoProgress)
+        }
 
-        samples
-    };
+        fn tile_finished(&mut self, tile: &FinishedTile) {
+            self.0.borrow_mut().push((tile.id, tile.replaced));
+        }
+    }
+
+    #[test]
+    fn retry_keeps_best_attempt() {
+        let finished = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let mut ctx = context();
+        ctx.pb = Box::new(FinishedTiles(finished.clone()));
+        ctx.params = SynthesisParams::preset(Quality::Fast);
+        let input = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([(x * 37 % 256) as u8, (y * 53 % 256) as u8, ((x ^ y) * 7 % 256) as u8, 255])
+        }));
+        let layout = TileLayout::Variation(WTileVariation::V4);
+        let samples = ctx.build_samples(SampleMode::Split, vec![input]).unwrap();
+        let opts = ctx.tile_options(&layout, SampleMode::Split, &samples, &Mask::default(), "");
+        let opts = opts.unwrap();
+        let mut tiles = ctx.build_n_w_tiles(&layout, &samples, &opts).unwrap();
+        finished.borrow_mut().clear();
+
+        // No score is below the threshold, so every tile is retried
+        let policy = RetryPolicy { threshold: -1.0, attempts: 1 };
+        let retries = ctx.retry_tiles(&policy, &mut tiles, &samples, &opts).unwrap();
+        assert_eq!(retries.len(), 4);
+        for retry in &retries {
+            assert_eq!(retry.attempts, 1);
+            assert!(retry.score <= retry.initial_score);
+        }
 
-    ctx.build_test_tiles(variation, &samples)
+        // Each retried tile is given again as a replacement
+        let ids: Vec<_> = retries.iter().map(|it| (it.id, true)).collect();
+        assert_eq!(*finished.borrow(), ids);
+
+        // The score of the kept attempt is the one of the tile in the set, the last
+        // retried tile having no neighbour replaced after it
+        let last = retries.last().unwrap();
+        let report = metrics::inspect(&tiles[0], Some(&samples[0][..])).unwrap();
+        assert_eq!(report.tiles[last.id].score(), last.score);
+    }
+
+    #[test]
+    fn mask_on_rectangular_tile() {
+        let (w, h) = (64, 32);
+        let mask = Mask::default().build([&gradient((w, h)); 4]).unwrap().to_luma();
+        let at = |x, y| mask.get_pixel(x, y)[0];
+
+        // Edges are kept, on the long and the short sides
+        for x in 0..w {
+            assert_eq!(at(x, 0), 255);
+            assert_eq!(at(x, h - 1), 255);
+        }
+        for y in 0..h {
+            assert_eq!(at(0, y), 255);
+            assert_eq!(at(w - 1, y), 255);
+        }
+        // The regions reach the middle of the edges along each axis, not only along
+        // the shorter one
+        assert_eq!(at(w / 2 - 3, 1), 255);
+        assert_eq!(at(1, h / 2 - 2), 255);
+        // The seams between the quadrants are synthesized
+        assert_eq!(at(w / 2, h / 2), 0);
+        assert_eq!(at(w / 2, h / 4), 0);
+        assert_eq!(at(w / 4, h / 2), 0);
+    }
 }
@@ -0,0 +1,285 @@
+use crate::cache::hash_image;
+use crate::error::Error;
+use crate::open_image;
+use crate::seam::{self, SeamOptions};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::path::Path;
+use texture_synthesis as ts;
+use ts::image::{imageops::FilterType, DynamicImage, GenericImageView, GrayImage, Luma};
+
+/// Shape of the region kept from each corner sample
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MaskShape {
+    /// Quarter circles centered on the tile corners
+    Circle,
+    /// Quarter diamonds centered on the tile corners
+    Diamond,
+    /// Squares anchored on the tile corners
+    Square,
+}
+
+impl std::str::FromStr for MaskShape {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "circle" => Ok(MaskShape::Circle),
+            "diamond" => Ok(MaskShape::Diamond),
+            "square" => Ok(MaskShape::Square),
+            _ => Err(Error::ParseError("Not a valid mask shape".into())),
+        }
+    }
+}
+
+impl std::fmt::Display for MaskShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let s = match self {
+            MaskShape::Circle => "circle",
+            MaskShape::Diamond => "diamond",
+            MaskShape::Square => "square",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+/// Options for a generated corner mask
+#[derive(Debug, Clone)]
+pub struct MaskOptions {
+    pub shape: MaskShape,
+    /// Size of the corner regions, relative to half of the tile along each axis.
+    /// `1.0` makes neighbouring corner regions touch at the middle of each edge.
+    ///
+    /// Whatever the shape and radius, the tile edges are always kept, so that
+    /// neighbouring tiles match, and a cross over the quadrant seams is always
+    /// synthesized.
+    pub radius: f32,
+    /// Width (in pixels) of the soft transition between kept and synthesized area
+    pub feather: u32,
+    /// Amplitude of the border perturbation, relative to the radius
+    pub noise: f32,
+    /// Seed of the border perturbation
+    pub seed: u64,
+}
+
+impl Default for MaskOptions {
+    fn default() -> MaskOptions {
+        MaskOptions { shape: MaskShape::Circle, radius: 1.0, feather: 0, noise: 0.0, seed: 0 }
+    }
+}
+
+/// Mask used to choose which part of the merged samples is kept in a tile.
+///
+/// White pixels are kept from the corner samples, black pixels are synthesized.
+#[derive(Clone)]
+pub enum Mask {
+    Shape(MaskOptions),
+    Image(DynamicImage),
+    /// Computed for each tile from the merged samples
+    Seam(SeamOptions),
+}
+
+impl std::fmt::Debug for Mask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mask::Shape(opts) => f.debug_tuple("Shape").field(opts).finish(),
+            Mask::Image(img) => f.debug_tuple("Image").field(&img.dimensions()).finish(),
+            Mask::Seam(opts) => f.debug_tuple("Seam").field(opts).finish(),
+        }
+    }
+}
+
+impl Default for Mask {
+    fn default() -> Mask {
+        Mask::Shape(MaskOptions::default())
+    }
+}
+
+impl Mask {
+    pub fn open<Q>(path: Q) -> Result<Mask, Error>
+    where
+        Q: AsRef<Path>,
+    {
+        Ok(Mask::Image(open_image(path)?))
+    }
+
+    /// Width of the feathered border, 0 if the mask is hard
+    pub(crate) fn feather(&self) -> u32 {
+        match self {
+            Mask::Shape(opts) => opts.feather,
+            Mask::Image(_) | Mask::Seam(_) => 0,
+        }
+    }
+
+    /// Whether the mask has to be rebuilt for each tile
+    pub(crate) fn is_per_tile(&self) -> bool {
+        matches!(self, Mask::Seam(_))
+    }
+
+    /// Key that identifies the mask in the cache
+    pub(crate) fn key(&self) -> String {
+        match self {
+            Mask::Shape(opts) => format!(
+                "{}+{}+{}+{}+{}",
+                opts.shape, opts.radius, opts.feather, opts.noise, opts.seed
+            ),
+            Mask::Image(img) => format!("image+{}", hash_image(img)),
+            Mask::Seam(opts) => format!("seam+{}+{}", opts.margin, opts.keep),
+        }
+    }
+
+    /// Build a soft mask for a tile made of the samples `corners` (a, b, c, d),
+    /// with values from 0 to 255
+    pub(crate) fn build(&self, corners: [&DynamicImage; 4]) -> Result<DynamicImage, Error> {
+        let (w, h) = corners[0].dimensions();
+        match self {
+            Mask::Shape(opts) => build_shape_mask(opts, (w, h)),
+            Mask::Seam(opts) => seam::build_seam_mask(opts, corners),
+            Mask::Image(img) => {
+                let img = if img.dimensions() != (w, h) {
+                    img.resize_exact(w, h, FilterType::Triangle)
+                } else {
+                    img.clone()
+                };
+                Ok(DynamicImage::ImageLuma8(img.to_luma()))
+            }
+        }
+    }
+}
+
+/// Turn a soft mask into the black and white mask used by texture-synthesis
+pub(crate) fn binarize(mask: &DynamicImage) -> DynamicImage {
+    let mut res = mask.to_luma();
+    for p in res.pixels_mut() {
+        p[0] = if p[0] >= 128 { 255 } else { 0 };
+    }
+    DynamicImage::ImageLuma8(res)
+}
+
+/// Blend the merged samples back over the synthesized tile, weighted by the soft mask
+pub(crate) fn blend(
+    generated: &DynamicImage,
+    merged: &DynamicImage,
+    mask: &DynamicImage,
+) -> DynamicImage {
+    let mask = mask.to_luma();
+    let merged = merged.to_rgba();
+    let mut res = generated.to_rgba();
+
+    for (x, y, p) in res.enumerate_pixels_mut() {
+        let t = mask.get_pixel(x, y)[0] as f32 / 255.0;
+        let m = merged.get_pixel(x, y);
+        for i in 0..4 {
+            p[i] = (p[i] as f32 * (1.0 - t) + m[i] as f32 * t).round() as u8;
+        }
+    }
+
+    DynamicImage::ImageRgba8(res)
+}
+
+/// Smooth 1D value noise in [-1, 1] over [0, 1]
+struct BorderNoise {
+    points: Vec<f32>,
+}
+
+impl BorderNoise {
+    fn new(rng: &mut StdRng) -> BorderNoise {
+        BorderNoise { points: (0..9).map(|_| rng.gen_range(-1.0, 1.0)).collect() }
+    }
+
+    fn at(&self, t: f32) -> f32 {
+        let n = (self.points.len() - 1) as f32;
+        let x = t.clamp(0.0, 1.0) * n;
+        let i = (x.floor() as usize).min(self.points.len() - 2);
+        let f = x - i as f32;
+        let f = (1.0 - (f * std::f32::consts::PI).cos()) / 2.0;
+        self.points[i] * (1.0 - f) + self.points[i + 1] * f
+    }
+}
+
+/// Half width of the cross always synthesized over the quadrant seams, relative
+/// to half of the tile, see [`SeamOptions::margin`]
+const CROSS_MARGIN: f32 = 0.125;
+
+/// Whether the pixel is on the outer ring of the tile, which is always kept
+fn on_edge(x: u32, y: u32, (w, h): (u32, u32)) -> bool {
+    x == 0 || y == 0 || x + 1 == w || y + 1 == h
+}
+
+/// Whether the pixel is in the cross over the quadrant seams, which narrows to
+/// nothing at the tile edges
+fn in_cross(x: u32, y: u32, (w, h): (u32, u32)) -> bool {
+    let (w2, h2) = (w as f32 / 2.0, h as f32 / 2.0);
+    let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+    let taper = |t: f32, len: f32| (std::f32::consts::PI * t / len).sin();
+    let band_x = CROSS_MARGIN * w2 * taper(py, h as f32);
+    let band_y = CROSS_MARGIN * h2 * taper(px, w as f32);
+    (px - w2).abs() < band_x || (py - h2).abs() < band_y
+}
+
+fn build_shape_mask(opts: &MaskOptions, (w, h): (u32, u32)) -> Result<DynamicImage, Error> {
+    if !(opts.radius > 0.0 && opts.radius.is_finite()) {
+        return Err(Error::InvalidInput(format!(
+            "Mask radius must be positive, got {}",
+            opts.radius
+        )));
+    }
+
+    let mut rng = StdRng::seed_from_u64(opts.seed);
+    let noises: Vec<_> = (0..4).map(|_| BorderNoise::new(&mut rng)).collect();
+
+    let mut img = GrayImage::new(w, h);
+
+    // Distances are measured in a space where both tile halves have the size of
+    // the shorter one, so on rectangular tiles the regions still meet at the
+    // middle of every edge.
+    let (w2, h2) = (w as f32 / 2.0, h as f32 / 2.0);
+    let unit = w2.min(h2);
+    let (sx, sy) = (unit / w2, unit / h2);
+    let radius = opts.radius * unit;
+    let corners = [(0.0, 0.0), (w as f32, 0.0), (0.0, h as f32), (w as f32, h as f32)];
+
+    for y in 0..h {
+        for x in 0..w {
+            let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+            // Signed distance to the nearest corner region, negative inside
+            let mut dist = f32::MAX;
+            for (corner, noise) in corners.iter().zip(&noises) {
+                let (dx, dy) = ((px - corner.0).abs() * sx, (py - corner.1).abs() * sy);
+                let d = match opts.shape {
+                    MaskShape::Circle => (dx * dx + dy * dy).sqrt(),
+                    MaskShape::Diamond => dx + dy,
+                    MaskShape::Square => dx.max(dy),
+                };
+
+                // Perturb the border, fading out towards the tile edges so that
+                // corner regions still meet where the edges are shared.
+                let theta = dy.atan2(dx);
+                let t = theta / std::f32::consts::FRAC_PI_2;
+                let fade = (2.0 * theta).sin();
+                let r = radius * (1.0 + opts.noise * noise.at(t) * fade);
+
+                dist = dist.min(d - r);
+            }
+
+            let v = if on_edge(x, y, (w, h)) {
+                1.0
+            } else if in_cross(x, y, (w, h)) {
+                0.0
+            } else if opts.feather == 0 {
+                if dist <= 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            } else {
+                (0.5 - dist / opts.feather as f32).clamp(0.0, 1.0)
+            };
+
+            img.put_pixel(x, y, Luma([(v * 255.0).round() as u8]));
+        }
+    }
+
+    Ok(DynamicImage::ImageLuma8(img))
+}
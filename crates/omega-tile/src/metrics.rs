@@ -0,0 +1,345 @@
+use crate::error::Error;
+use crate::wtile::{Direction, WTile};
+use std::fmt;
+use texture_synthesis as ts;
+use ts::image::{DynamicImage, RgbaImage};
+
+/// Seam and corner quality of one tile, lower is better
+#[derive(Debug, Clone)]
+pub struct TileQuality {
+    pub id: usize,
+    /// Number of edges with compatible neighbours that were measured
+    pub edges: usize,
+    /// Mean squared color difference across the edges
+    pub edge_mse: f64,
+    /// Mean squared difference between the gradient across the edges
+    /// and the gradient just inside the tiles
+    pub gradient: f64,
+    /// Largest edge mean squared error with a single neighbour
+    pub worst_edge: f64,
+    /// Mean squared deviation of the corner regions from the source samples
+    pub corner_deviation: Option<f64>,
+}
+
+impl TileQuality {
+    /// Single number used to rank the tiles
+    pub fn score(&self) -> f64 {
+        self.gradient + self.corner_deviation.unwrap_or(0.0)
+    }
+}
+
+pub struct QualityReport {
+    pub tiles: Vec<TileQuality>,
+}
+
+impl QualityReport {
+    /// The `n` tiles with the highest score
+    pub fn worst(&self, n: usize) -> Vec<&TileQuality> {
+        let mut res: Vec<_> = self.tiles.iter().collect();
+        res.sort_by(|a, b| b.score().partial_cmp(&a.score()).unwrap_or(std::cmp::Ordering::Equal));
+        res.truncate(n);
+        res
+    }
+}
+
+impl fmt::Display for QualityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>4} {:>5} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "id", "edges", "edge mse", "gradient", "worst", "corners", "score"
+        )?;
+        for t in &self.tiles {
+            let corners = match t.corner_deviation {
+                Some(it) => format!("{:.2}", it),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:>4} {:>5} {:>10.2} {:>10.2} {:>10.2} {:>10} {:>10.2}",
+                t.id,
+                t.edges,
+                t.edge_mse,
+                t.gradient,
+                t.worst_edge,
+                corners,
+                t.score()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Accum {
+    edges: usize,
+    mse: f64,
+    gradient: f64,
+    worst: f64,
+}
+
+impl Accum {
+    fn add(&mut self, (mse, gradient): (f64, f64)) {
+        self.edges += 1;
+        self.mse += mse;
+        self.gradient += gradient;
+        self.worst = self.worst.max(mse);
+    }
+
+    fn finish(self, id: usize, corner_deviation: Option<f64>) -> TileQuality {
+        let n = self.edges.max(1) as f64;
+        TileQuality {
+            id,
+            edges: self.edges,
+            edge_mse: self.mse / n,
+            gradient: self.gradient / n,
+            worst_edge: self.worst,
+            corner_deviation,
+        }
+    }
+}
+
+/// Threshold above which tiles are synthesized again with another seed
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Highest acceptable [`TileQuality::score`]
+    pub threshold: f64,
+    /// Maximum number of new attempts for each tile
+    pub attempts: u32,
+}
+
+/// A tile which was synthesized again because of its score
+#[derive(Debug, Clone)]
+pub struct TileRetry {
+    pub id: usize,
+    /// Number of new attempts made
+    pub attempts: u32,
+    /// Score of the first attempt
+    pub initial_score: f64,
+    /// Score of the kept attempt
+    pub score: f64,
+}
+
+/// Measure the seam of `first` followed by `second` along the x axis.
+///
+/// Vertical seams use transposed coordinates.
+fn measure_seam(first: &RgbaImage, second: &RgbaImage, vertical: bool) -> (f64, f64) {
+    let (w, h) = first.dimensions();
+    let (len, depth) = if vertical { (w, h) } else { (h, w) };
+    if depth < 2 || len == 0 {
+        return (0.0, 0.0);
+    }
+
+    let at = |img: &RgbaImage, i: u32, k: u32, c: usize| -> f64 {
+        let p = if vertical { img.get_pixel(k, i) } else { img.get_pixel(i, k) };
+        p[c] as f64
+    };
+
+    let (mut mse, mut gradient) = (0.0, 0.0);
+    for k in 0..len {
+        for c in 0..3 {
+            let a1 = at(first, depth - 2, k, c);
+            let a0 = at(first, depth - 1, k, c);
+            let b0 = at(second, 0, k, c);
+            let b1 = at(second, 1, k, c);
+
+            let across = b0 - a0;
+            let inside = ((a0 - a1) + (b1 - b0)) / 2.0;
+            mse += across * across;
+            gradient += (across - inside) * (across - inside);
+        }
+    }
+
+    let n = (len * 3) as f64;
+    (mse / n, gradient / n)
+}
+
+/// Mean squared deviation of the corner regions of a tile from the source samples.
+///
+/// Only a square of an eighth of the tile at each corner is compared, which is
+/// kept from the samples by every mask that produces seamless tiles.
+fn corner_deviation(tile: &RgbaImage, samples: &[RgbaImage], corners: [usize; 4]) -> f64 {
+    let (w, h) = tile.dimensions();
+    let (w2, h2) = (w / 2, h / 2);
+    let size = (w.min(h) / 8).max(1);
+
+    let mut sum = 0.0;
+    let mut n = 0;
+    for y in (0..size).chain(h - size..h) {
+        for x in (0..size).chain(w - size..w) {
+            // Same layout as `merge_samples`
+            let (id, sx, sy) = match (x < w2, y < h2) {
+                (true, true) => (corners[0], x + w2, y + h2),
+                (false, true) => (corners[1], x - w2, y + h2),
+                (true, false) => (corners[2], x + w2, y - h2),
+                (false, false) => (corners[3], x - w2, y - h2),
+            };
+            let (p, q) = (tile.get_pixel(x, y), samples[id].get_pixel(sx, sy));
+            for c in 0..3 {
+                let d = p[c] as f64 - q[c] as f64;
+                sum += d * d;
+            }
+            n += 3;
+        }
+    }
+
+    sum / n as f64
+}
+
+fn to_images(
+    tiles: &[WTile],
+    samples: Option<&[DynamicImage]>,
+) -> Result<(Vec<RgbaImage>, Option<Vec<RgbaImage>>), Error> {
+    let imgs: Vec<RgbaImage> = tiles.iter().map(|it| it.img.to_rgba()).collect();
+    let dim = match imgs.first() {
+        Some(it) => it.dimensions(),
+        None => return Ok((imgs, None)),
+    };
+    if imgs.iter().any(|it| it.dimensions() != dim) {
+        return Err(Error::SizeMismatch);
+    }
+
+    let samples: Option<Vec<RgbaImage>> =
+        samples.map(|it| it.iter().map(|s| s.to_rgba()).collect());
+    if let Some(samples) = &samples {
+        // Tile corners are colors between 0 and 3, each picking one sample
+        if samples.len() != 4 {
+            return Err(Error::InvalidInput(format!("Expected 4 samples, got {}", samples.len())));
+        }
+        if samples.iter().any(|it| it.dimensions() != dim) {
+            return Err(Error::SizeMismatch);
+        }
+    }
+
+    Ok((imgs, samples))
+}
+
+/// Measure how well every tile joins its compatible neighbours, and how much its
+/// corners deviate from the `samples` it was built from.
+pub fn inspect(tiles: &[WTile], samples: Option<&[DynamicImage]>) -> Result<QualityReport, Error> {
+    let (imgs, samples) = to_images(tiles, samples)?;
+
+    let mut accums: Vec<Accum> = tiles.iter().map(|_| Accum::default()).collect();
+    for (i, t) in tiles.iter().enumerate() {
+        for (j, u) in tiles.iter().enumerate() {
+            if t.is_connectable(Direction::East, u) {
+                let m = measure_seam(&imgs[i], &imgs[j], false);
+                accums[i].add(m);
+                accums[j].add(m);
+            }
+            if t.is_connectable(Direction::South, u) {
+                let m = measure_seam(&imgs[i], &imgs[j], true);
+                accums[i].add(m);
+                accums[j].add(m);
+            }
+        }
+    }
+
+    let res = tiles
+        .iter()
+        .zip(accums)
+        .enumerate()
+        .map(|(id, (t, acc))| {
+            let corners = samples.as_ref().map(|s| corner_deviation(&imgs[id], s, t.corners()));
+            acc.finish(id, corners)
+        })
+        .collect();
+
+    Ok(QualityReport { tiles: res })
+}
+
+/// Same as [`inspect`], for a single tile
+pub(crate) fn inspect_tile(
+    tiles: &[WTile],
+    samples: Option<&[DynamicImage]>,
+    id: usize,
+) -> Result<TileQuality, Error> {
+    let (imgs, samples) = to_images(tiles, samples)?;
+    let t = &tiles[id];
+
+    let mut acc = Accum::default();
+    for (j, u) in tiles.iter().enumerate() {
+        if t.is_connectable(Direction::East, u) {
+            acc.add(measure_seam(&imgs[id], &imgs[j], false));
+        }
+        if u.is_connectable(Direction::East, t) {
+            acc.add(measure_seam(&imgs[j], &imgs[id], false));
+        }
+        if t.is_connectable(Direction::South, u) {
+            acc.add(measure_seam(&imgs[id], &imgs[j], true));
+        }
+        if u.is_connectable(Direction::South, t) {
+            acc.add(measure_seam(&imgs[j], &imgs[id], true));
+        }
+    }
+
+    let corners = samples.as_ref().map(|s| corner_deviation(&imgs[id], s, t.corners()));
+    Ok(acc.finish(id, corners))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ts::image::Rgba;
+
+    const GRAYS: [u8; 4] = [0, 60, 120, 180];
+
+    /// Tile made of its corner samples, each one a flat gray, brightened by `offset`
+    fn tile((a, b, c, d): (usize, usize, usize, usize), offset: u8) -> WTile {
+        let img = RgbaImage::from_fn(16, 16, |x, y| {
+            let corner = match (x < 8, y < 8) {
+                (true, true) => a,
+                (false, true) => b,
+                (true, false) => c,
+                (false, false) => d,
+            };
+            let v = GRAYS[corner] + offset;
+            Rgba([v, v, v, 255])
+        });
+        WTile::new(DynamicImage::ImageRgba8(img), a, b, c, d)
+    }
+
+    #[test]
+    fn scores_of_known_tiles() {
+        let samples: Vec<_> = GRAYS
+            .iter()
+            .map(|&v| DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba([v, v, v, 255]))))
+            .collect();
+        // The east edge of each tile matches the west edge of the others, the last
+        // tile is 40 brighter than its samples
+        let tiles = vec![tile((0, 1, 2, 3), 0), tile((1, 0, 3, 2), 0), tile((1, 0, 3, 2), 40)];
+        let report = inspect(&tiles, Some(&samples)).unwrap();
+
+        let [first, second, bright] = [&report.tiles[0], &report.tiles[1], &report.tiles[2]];
+        assert_eq!(second.edges, 2);
+        assert_eq!(second.score(), 0.0);
+        assert_eq!(second.corner_deviation, Some(0.0));
+        assert_eq!(bright.edges, 2);
+        assert_eq!(bright.edge_mse, 1600.0);
+        assert_eq!(bright.gradient, 1600.0);
+        assert_eq!(bright.corner_deviation, Some(1600.0));
+        // Joined to both, both ways
+        assert_eq!(first.edges, 4);
+        assert_eq!(first.edge_mse, 800.0);
+        assert_eq!(first.worst_edge, 1600.0);
+        assert_eq!(first.corner_deviation, Some(0.0));
+        assert_eq!(report.worst(1)[0].id, 2);
+
+        // A single tile is measured like in the whole report
+        for (id, t) in report.tiles.iter().enumerate() {
+            let alone = inspect_tile(&tiles, Some(&samples), id).unwrap();
+            assert_eq!((alone.edges, alone.score()), (t.edges, t.score()));
+        }
+    }
+
+    #[test]
+    fn inspect_needs_four_samples() {
+        let tiles = vec![WTile::new(DynamicImage::new_rgba8(8, 8), 0, 0, 0, 0)];
+        let samples = vec![DynamicImage::new_rgba8(8, 8); 3];
+        match inspect(&tiles, Some(&samples)) {
+            Err(Error::InvalidInput(_)) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("3 samples can not give the colors of the corners"),
+        }
+    }
+}
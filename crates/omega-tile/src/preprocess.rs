@@ -0,0 +1,268 @@
+use crate::error::Error;
+use texture_synthesis as ts;
+use ts::image::{
+    imageops::{self, FilterType},
+    DynamicImage, GenericImageView, GrayImage, ImageBuffer, Luma, Pixel, RgbaImage,
+};
+
+/// Preparation of the input before the samples are built
+#[derive(Debug, Clone, Default)]
+pub struct Preprocess {
+    /// Crop to the largest centered square
+    pub square: bool,
+    /// Crop the dimensions down to a size that gives tiles with even dimensions
+    pub even: bool,
+    /// Resize the input so the tiles have this width, which must be even, keeping
+    /// the aspect ratio
+    pub tile_size: Option<u32>,
+    /// Resize the input to a texel density, exclusive with `tile_size`
+    pub density: Option<TexelDensity>,
+    /// Make the input seamless with an offset-and-heal pass
+    pub seamless: bool,
+}
+
+/// Physical scale of the input, used to pick the resolution of the tiles
+#[derive(Debug, Clone)]
+pub struct TexelDensity {
+    /// Width of the area shown by the input, in meters
+    pub source_width: f32,
+    /// Wanted number of texels per meter
+    pub texels_per_meter: f32,
+}
+
+impl Preprocess {
+    /// Key that identifies the preprocessing in the cache
+    pub(crate) fn key(&self) -> String {
+        let size = match self.tile_size {
+            Some(it) => format!("w{}", it),
+            None => "none".to_string(),
+        };
+        let mut key = format!("{}+{}+{}+{}", self.square, self.even, size, self.seamless);
+        if let Some(d) = &self.density {
+            key.push_str(&format!("+density{}+{}", d.source_width, d.texels_per_meter));
+        }
+        key
+    }
+
+    /// Apply cropping and resizing.
+    ///
+    /// `tiles_per_side` is the number of tiles the input is split into along each axis.
+    pub(crate) fn resize<I>(&self, img: &I, tiles_per_side: u32) -> Result<Resized<I>, Error>
+    where
+        I: GenericImageView,
+        I::Pixel: 'static,
+    {
+        let (w, h) = img.dimensions();
+        if w == 0 || h == 0 {
+            return Err(Error::EmptyImage);
+        }
+
+        let mut img = if self.square && w != h {
+            let side = w.min(h);
+            crop(img, (w - side) / 2, (h - side) / 2, side, side)
+        } else {
+            crop(img, 0, 0, w, h)
+        };
+
+        // Tiles are made of 4 quadrants, so their sides must be even
+        let step = 2 * tiles_per_side;
+
+        let width = match (self.tile_size, &self.density) {
+            (Some(_), Some(_)) => {
+                return Err(Error::InvalidInput(
+                    "Tile size and texel density can not be used together".into(),
+                ))
+            }
+            (Some(size), None) if size % 2 != 0 => {
+                return Err(Error::InvalidInput(format!("Tile size must be even, got {}", size)))
+            }
+            (Some(size), None) => Some(size * tiles_per_side),
+            (None, Some(d)) => {
+                // Width in meters of what is left after cropping
+                let meters = img.width() as f32 * d.source_width / w as f32;
+                Some(round_to((meters * d.texels_per_meter).round() as u64, step))
+            }
+            (None, None) => None,
+        };
+
+        if let Some(width) = width {
+            let (w, h) = img.dimensions();
+            if width == 0 {
+                return Err(Error::EmptyImage);
+            }
+            let height = round_to(u64::from(h) * u64::from(width) / u64::from(w), step);
+            img = imageops::resize(&img, width, height.max(step), FilterType::Lanczos3);
+        }
+
+        let (w, h) = img.dimensions();
+        if self.even && (w % step != 0 || h % step != 0) {
+            img = crop(&img, 0, 0, w - w % step, h - h % step);
+        }
+
+        if img.width() == 0 || img.height() == 0 {
+            return Err(Error::EmptyImage);
+        }
+
+        Ok(img)
+    }
+}
+
+/// Image buffer with the pixels of `I`
+pub(crate) type Resized<I> = ImageBuffer<
+    <I as GenericImageView>::Pixel,
+    Vec<<<I as GenericImageView>::Pixel as Pixel>::Subpixel>,
+>;
+
+/// Copy of a part of the image
+fn crop<I>(img: &I, x: u32, y: u32, w: u32, h: u32) -> Resized<I>
+where
+    I: GenericImageView,
+    I::Pixel: 'static,
+{
+    ImageBuffer::from_fn(w, h, |px, py| img.get_pixel(x + px, y + py))
+}
+
+/// Nearest multiple of `step`
+fn round_to(n: u64, step: u32) -> u32 {
+    let step = u64::from(step);
+    ((n + step / 2) / step * step) as u32
+}
+
+/// Check the input gives tiles with even sides, once split into `tiles_per_side`
+/// along each axis, so the 4 quadrants of a tile fill it exactly
+pub(crate) fn check_splitable((w, h): (u32, u32), tiles_per_side: u32) -> Result<(), Error> {
+    let step = 2 * tiles_per_side;
+    if w == 0 || h == 0 {
+        Err(Error::EmptyImage)
+    } else if w % step != 0 || h % step != 0 {
+        Err(Error::OddSize((w, h)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Wrap the image around by half of its size, moving the borders to the center
+pub(crate) fn offset_half(img: &DynamicImage) -> DynamicImage {
+    let src = img.to_rgba();
+    let (w, h) = src.dimensions();
+    let res = RgbaImage::from_fn(w, h, |x, y| *src.get_pixel((x + w / 2) % w, (y + h / 2) % h));
+    DynamicImage::ImageRgba8(res)
+}
+
+/// Wrap the image around by half of its size, undoing `offset_half`
+pub(crate) fn offset_back(img: &DynamicImage) -> DynamicImage {
+    let src = img.to_rgba();
+    let (w, h) = src.dimensions();
+    let res =
+        RgbaImage::from_fn(w, h, |x, y| *src.get_pixel((x + w - w / 2) % w, (y + h - h / 2) % h));
+    DynamicImage::ImageRgba8(res)
+}
+
+/// Mask keeping everything but a cross over the center lines, where the borders
+/// meet after `offset_half`
+pub(crate) fn heal_mask((w, h): (u32, u32)) -> DynamicImage {
+    let band = (w.min(h) / 16).max(2) as i64;
+    let (cx, cy) = ((w / 2) as i64, (h / 2) as i64);
+    let res = GrayImage::from_fn(w, h, |x, y| {
+        let (dx, dy) = ((x as i64 - cx).abs(), (y as i64 - cy).abs());
+        if dx < band || dy < band {
+            Luma([0])
+        } else {
+            Luma([255])
+        }
+    });
+    DynamicImage::ImageLuma8(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ts::image::Rgba;
+
+    /// Image where every pixel encodes its coordinates
+    fn gradient((w, h): (u32, u32)) -> RgbaImage {
+        RgbaImage::from_fn(w, h, |x, y| Rgba([x as u8, y as u8, 0, 255]))
+    }
+
+    #[test]
+    fn crop_centered_square() {
+        let opts = Preprocess { square: true, ..Preprocess::default() };
+        let img = opts.resize(&gradient((48, 32)), 2).unwrap();
+        assert_eq!(img.dimensions(), (32, 32));
+        assert_eq!(img.get_pixel(0, 0), &Rgba([8, 0, 0, 255]));
+        assert_eq!(img.get_pixel(31, 31), &Rgba([39, 31, 0, 255]));
+
+        let img = opts.resize(&gradient((20, 36)), 2).unwrap();
+        assert_eq!(img.dimensions(), (20, 20));
+        assert_eq!(img.get_pixel(0, 0), &Rgba([0, 8, 0, 255]));
+    }
+
+    #[test]
+    fn round_down_to_even_tiles() {
+        let opts = Preprocess { even: true, ..Preprocess::default() };
+        let img = opts.resize(&gradient((30, 22)), 2).unwrap();
+        // Split into 2 tiles per side, the sides are multiples of 4
+        assert_eq!(img.dimensions(), (28, 20));
+        assert_eq!(img.get_pixel(27, 19), &Rgba([27, 19, 0, 255]));
+        assert!(check_splitable(img.dimensions(), 2).is_ok());
+
+        // Already even inputs are left as they are
+        let img = opts.resize(&gradient((24, 16)), 2).unwrap();
+        assert_eq!(img.dimensions(), (24, 16));
+    }
+
+    #[test]
+    fn resize_to_tile_size() {
+        let opts = Preprocess { tile_size: Some(16), ..Preprocess::default() };
+        let img = opts.resize(&gradient((64, 48)), 2).unwrap();
+        assert_eq!(img.dimensions(), (32, 24));
+
+        // The height keeps the aspect ratio, rounded to give even tiles
+        let img = opts.resize(&gradient((100, 30)), 2).unwrap();
+        assert_eq!(img.dimensions(), (32, 8));
+        assert!(check_splitable(img.dimensions(), 2).is_ok());
+
+        let odd = Preprocess { tile_size: Some(15), ..Preprocess::default() };
+        assert!(matches!(odd.resize(&gradient((64, 48)), 2), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn resize_to_texel_density() {
+        let density = |source_width, texels_per_meter| Preprocess {
+            density: Some(TexelDensity { source_width, texels_per_meter }),
+            ..Preprocess::default()
+        };
+        let img = density(2.0, 16.0).resize(&gradient((100, 50)), 2).unwrap();
+        assert_eq!(img.dimensions(), (32, 16));
+
+        // The width is rounded to the nearest size giving even tiles
+        let img = density(1.0, 30.0).resize(&gradient((64, 64)), 2).unwrap();
+        assert_eq!(img.dimensions(), (32, 32));
+        let img = density(1.0, 29.0).resize(&gradient((64, 64)), 2).unwrap();
+        assert_eq!(img.dimensions(), (28, 28));
+
+        // Only the width left after cropping is resampled
+        let mut square = density(3.0, 10.0);
+        square.square = true;
+        let img = square.resize(&gradient((48, 32)), 2).unwrap();
+        assert_eq!(img.dimensions(), (20, 20));
+
+        let mut both = density(1.0, 32.0);
+        both.tile_size = Some(16);
+        assert!(matches!(both.resize(&gradient((64, 64)), 2), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn refuse_odd_and_empty_inputs() {
+        assert!(matches!(check_splitable((30, 24), 2), Err(Error::OddSize((30, 24)))));
+        assert!(matches!(check_splitable((24, 21), 1), Err(Error::OddSize((24, 21)))));
+        assert!(matches!(check_splitable((0, 24), 2), Err(Error::EmptyImage)));
+        assert!(check_splitable((24, 20), 2).is_ok());
+
+        let opts = Preprocess::default();
+        assert!(matches!(opts.resize(&gradient((0, 0)), 2), Err(Error::EmptyImage)));
+        // Rounding down leaves nothing of a too small input
+        let even = Preprocess { even: true, ..Preprocess::default() };
+        assert!(matches!(even.resize(&gradient((3, 8)), 2), Err(Error::EmptyImage)));
+    }
+}
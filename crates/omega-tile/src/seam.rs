@@ -0,0 +1,279 @@
+use crate::error::Error;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use texture_synthesis as ts;
+use ts::image::{DynamicImage, GrayImage, Luma, Rgba, RgbaImage};
+
+/// Options for a mask computed from the merged samples
+#[derive(Debug, Clone)]
+pub struct SeamOptions {
+    /// Half width of the band always synthesized around the quadrant seams,
+    /// relative to half of the tile. The band narrows to nothing at the tile edges.
+    pub margin: f32,
+    /// How strongly the cut is pulled towards the seams, which keeps more of the samples
+    pub keep: f32,
+}
+
+impl Default for SeamOptions {
+    fn default() -> SeamOptions {
+        SeamOptions { margin: 0.125, keep: 1.0 }
+    }
+}
+
+/// A quadrant of the merged image, with the tile corner it belongs to
+struct Quadrant {
+    x0: u32,
+    y0: u32,
+    w: u32,
+    h: u32,
+    /// Whether the tile corner is on the right / bottom side of the quadrant
+    flip: (bool, bool),
+}
+
+impl Quadrant {
+    /// Map quadrant local coordinates (corner at origin) to image coordinates
+    fn to_image(&self, x: u32, y: u32) -> (u32, u32) {
+        let x = if self.flip.0 { self.w - 1 - x } else { x };
+        let y = if self.flip.1 { self.h - 1 - y } else { y };
+        (self.x0 + x, self.y0 + y)
+    }
+}
+
+/// Build a mask keeping, for each corner sample, the region cut off by a
+/// minimal cost path from the middle of its horizontal edge to the middle of its
+/// vertical edge.
+///
+/// `corners` are the samples (a, b, c, d) merged into the tile. Shifted by half
+/// of their size, as in the merged image, each of them covers the whole tile, so
+/// the path cost is the color difference between a sample and the samples of the
+/// neighbouring quadrants: a minimal error boundary cut, going where they agree.
+pub(crate) fn build_seam_mask(
+    opts: &SeamOptions,
+    corners: [&DynamicImage; 4],
+) -> Result<DynamicImage, Error> {
+    let samples: Vec<RgbaImage> = corners.iter().map(|it| it.to_rgba()).collect();
+    let (w, h) = samples[0].dimensions();
+    let (w2, h2) = (w / 2, h / 2);
+    if w2 < 2 || h2 < 2 || samples.iter().any(|it| it.dimensions() != (w, h)) {
+        return Err(Error::SizeMismatch);
+    }
+
+    let mut res = GrayImage::new(w, h);
+
+    let quadrants = [
+        Quadrant { x0: 0, y0: 0, w: w2, h: h2, flip: (false, false) },
+        Quadrant { x0: w2, y0: 0, w: w - w2, h: h2, flip: (true, false) },
+        Quadrant { x0: 0, y0: h2, w: w2, h: h - h2, flip: (false, true) },
+        Quadrant { x0: w2, y0: h2, w: w - w2, h: h - h2, flip: (true, true) },
+    ];
+
+    for (i, q) in quadrants.iter().enumerate() {
+        // The quadrants across the vertical and the horizontal seams
+        let error = |ix: u32, iy: u32| -> f32 {
+            let at = |s: usize| samples[s].get_pixel((ix + w2) % w, (iy + h2) % h);
+            let p = at(i);
+            let diff = |q: &Rgba<u8>| -> f32 {
+                let d: i32 = (0..3).map(|c| (p[c] as i32 - q[c] as i32).pow(2)).sum();
+                (d as f32).sqrt()
+            };
+            (diff(at(i ^ 1)) + diff(at(i ^ 2))) / 2.0
+        };
+        let kept = cut_quadrant(opts, q, error);
+        for y in 0..q.h {
+            for x in 0..q.w {
+                if kept[(y * q.w + x) as usize] {
+                    let (ix, iy) = q.to_image(x, y);
+                    res.put_pixel(ix, iy, Luma([255]));
+                }
+            }
+        }
+    }
+
+    Ok(DynamicImage::ImageLuma8(res))
+}
+
+/// Whether a pixel, in quadrant local coordinates, is in the band always synthesized.
+/// The seams are the far column (x = w) and the far row (y = h).
+fn in_band(opts: &SeamOptions, (w, h): (usize, usize), x: usize, y: usize) -> bool {
+    let margin_x = opts.margin * w as f32;
+    let margin_y = opts.margin * h as f32;
+    let band_x = margin_x * (std::f32::consts::PI * (y as f32 + 0.5) / (2.0 * h as f32)).sin();
+    let band_y = margin_y * (std::f32::consts::PI * (x as f32 + 0.5) / (2.0 * w as f32)).sin();
+    (w as f32 - x as f32 - 0.5) < band_x || (h as f32 - y as f32 - 0.5) < band_y
+}
+
+/// Cut a quadrant along the path of minimal `error`, given in image coordinates
+fn cut_quadrant(opts: &SeamOptions, q: &Quadrant, error: impl Fn(u32, u32) -> f32) -> Vec<bool> {
+    let (w, h) = (q.w as usize, q.h as usize);
+    let idx = |x: usize, y: usize| y * w + x;
+
+    let in_band = |x: usize, y: usize| in_band(opts, (w, h), x, y);
+
+    let mut cost = vec![0u32; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let (ix, iy) = q.to_image(x as u32, y as u32);
+            let error = error(ix, iy);
+
+            // Distance to the seams, normalized by the quadrant size
+            let dist = ((w - x) as f32 / w as f32).min((h - y) as f32 / h as f32);
+            cost[idx(x, y)] = 1 + error as u32 + (opts.keep * 255.0 * dist) as u32;
+        }
+    }
+
+    // Dijkstra from the middle of the horizontal edge to the middle of the vertical edge
+    let start = idx(w - 1, 0);
+    let goal = idx(0, h - 1);
+
+    let mut dist = vec![u32::MAX; w * h];
+    let mut prev = vec![usize::MAX; w * h];
+    let mut heap = BinaryHeap::new();
+    dist[start] = cost[start];
+    heap.push(Reverse((cost[start], start)));
+
+    while let Some(Reverse((d, i))) = heap.pop() {
+        if i == goal {
+            break;
+        }
+        if d > dist[i] {
+            continue;
+        }
+        let (x, y) = ((i % w) as isize, (i / w) as isize);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (nx, ny) = (x + dx, y + dy);
+                if (dx == 0 && dy == 0) || nx < 0 || ny < 0 || nx >= w as isize || ny >= h as isize
+                {
+                    continue;
+                }
+                let (nx, ny) = (nx as usize, ny as usize);
+                let n = idx(nx, ny);
+                if in_band(nx, ny) && n != goal {
+                    continue;
+                }
+                let nd = d.saturating_add(cost[n]);
+                if nd < dist[n] {
+                    dist[n] = nd;
+                    prev[n] = i;
+                    heap.push(Reverse((nd, n)));
+                }
+            }
+        }
+    }
+
+    let mut is_path = vec![false; w * h];
+    if dist[goal] == u32::MAX {
+        // No way around the band, keep the quadrant outside of it
+        return (0..w * h).map(|i| !in_band(i % w, i / w)).collect();
+    }
+    let mut cur = goal;
+    while cur != usize::MAX {
+        is_path[cur] = true;
+        cur = prev[cur];
+    }
+
+    // Everything reachable from the inner corner without crossing the path is synthesized
+    let mut kept = vec![true; w * h];
+    let inner = idx(w - 1, h - 1);
+    let mut queue = VecDeque::new();
+    if !is_path[inner] {
+        kept[inner] = false;
+        queue.push_back(inner);
+    }
+    while let Some(i) = queue.pop_front() {
+        let (x, y) = (i % w, i / w);
+        let mut visit = |n: usize| {
+            if kept[n] && !is_path[n] {
+                kept[n] = false;
+                queue.push_back(n);
+            }
+        };
+        if x > 0 {
+            visit(idx(x - 1, y));
+        }
+        if x + 1 < w {
+            visit(idx(x + 1, y));
+        }
+        if y > 0 {
+            visit(idx(x, y - 1));
+        }
+        if y + 1 < h {
+            visit(idx(x, y + 1));
+        }
+    }
+
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 16;
+
+    fn quadrant() -> Quadrant {
+        Quadrant { x0: 0, y0: 0, w: SIZE, h: SIZE, flip: (false, false) }
+    }
+
+    fn noise(x: u32, y: u32) -> f32 {
+        ((x * 7 + y * 13) % 50) as f32
+    }
+
+    #[test]
+    fn cut_inside_margin() {
+        let opts = SeamOptions { margin: 0.25, keep: 1.0 };
+        let kept = cut_quadrant(&opts, &quadrant(), noise);
+        let n = SIZE as usize;
+        let mut band = 0;
+        for y in 0..n {
+            for x in 0..n {
+                if in_band(&opts, (n, n), x, y) {
+                    band += 1;
+                    assert!(!kept[y * n + x], "({}, {}) is kept in the band", x, y);
+                }
+            }
+        }
+        assert!(band > 0);
+    }
+
+    #[test]
+    fn tile_edges_kept() {
+        for &keep in &[0.0, 1.0] {
+            let opts = SeamOptions { margin: 0.25, keep };
+            let kept = cut_quadrant(&opts, &quadrant(), noise);
+            let n = SIZE as usize;
+            for i in 0..n {
+                assert!(kept[i], "({}, 0) is not kept", i);
+                assert!(kept[i * n], "(0, {}) is not kept", i);
+            }
+        }
+    }
+
+    #[test]
+    fn cut_follows_lowest_error() {
+        // The samples agree along the boundary between a left and a right region,
+        // from the top edge down column `c` to the bottom row
+        let c = 5;
+        let error = |x: u32, y: u32| -> f32 {
+            let on_path = (y == 0 && x >= c) || x == c || (y == SIZE - 1 && x <= c);
+            if on_path {
+                0.0
+            } else {
+                200.0
+            }
+        };
+        let opts = SeamOptions { margin: 0.0, keep: 0.0 };
+        let kept = cut_quadrant(&opts, &quadrant(), error);
+        let n = SIZE as usize;
+        for y in 1..n - 1 {
+            for x in 0..n {
+                let k = kept[y * n + x];
+                match x.cmp(&(c as usize)) {
+                    std::cmp::Ordering::Less => assert!(k, "({}, {}) is not kept", x, y),
+                    std::cmp::Ordering::Greater => assert!(!k, "({}, {}) is kept", x, y),
+                    std::cmp::Ordering::Equal => {}
+                }
+            }
+        }
+    }
+}
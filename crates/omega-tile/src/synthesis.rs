@@ -0,0 +1,123 @@
+use crate::error::Error;
+use texture_synthesis as ts;
+
+/// Quality presets for [`SynthesisParams`]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Quality {
+    Fast,
+    Balanced,
+    High,
+}
+
+impl std::str::FromStr for Quality {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast" => Ok(Quality::Fast),
+            "balanced" => Ok(Quality::Balanced),
+            "high" => Ok(Quality::High),
+            _ => Err(Error::ParseError("Not a valid quality".into())),
+        }
+    }
+}
+
+impl std::fmt::Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let s = match self {
+            Quality::Fast => "fast",
+            Quality::Balanced => "balanced",
+            Quality::High => "high",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+/// Tuning parameters passed to texture-synthesis
+#[derive(Debug, Clone, PartialEq)]
+pub struct SynthesisParams {
+    pub nearest_neighbors: u32,
+    pub random_sample_locations: u64,
+    pub backtrack_stages: u32,
+    pub backtrack_percent: f32,
+    pub cauchy_dispersion: f32,
+    pub guide_alpha: f32,
+}
+
+impl SynthesisParams {
+    pub fn preset(quality: Quality) -> SynthesisParams {
+        match quality {
+            Quality::Fast => SynthesisParams {
+                nearest_neighbors: 20,
+                random_sample_locations: 20,
+                backtrack_stages: 2,
+                backtrack_percent: 0.35,
+                cauchy_dispersion: 1.0,
+                guide_alpha: 0.8,
+            },
+            // Same as the texture-synthesis defaults
+            Quality::Balanced => SynthesisParams {
+                nearest_neighbors: 50,
+                random_sample_locations: 50,
+                backtrack_stages: 5,
+                backtrack_percent: 0.5,
+                cauchy_dispersion: 1.0,
+                guide_alpha: 0.8,
+            },
+            Quality::High => SynthesisParams {
+                nearest_neighbors: 100,
+                random_sample_locations: 100,
+                backtrack_stages: 8,
+                backtrack_percent: 0.5,
+                cauchy_dispersion: 1.0,
+                guide_alpha: 0.8,
+            },
+        }
+    }
+
+    /// Key that identifies the parameters in the cache
+    pub(crate) fn key(&self) -> String {
+        format!(
+            "{}+{}+{}+{}+{}+{}",
+            self.nearest_neighbors,
+            self.random_sample_locations,
+            self.backtrack_stages,
+            self.backtrack_percent,
+            self.cauchy_dispersion,
+            self.guide_alpha
+        )
+    }
+
+    /// Refuse parameters which are not finite numbers
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        let floats = [
+            ("backtrack percent", self.backtrack_percent),
+            ("cauchy dispersion", self.cauchy_dispersion),
+            ("guide alpha", self.guide_alpha),
+        ];
+        match floats.iter().find(|(_, it)| !it.is_finite()) {
+            Some((name, it)) => Err(Error::InvalidInput(format!(
+                "The {} must be a finite number, got {}",
+                name, it
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn apply<'a>(&self, builder: ts::SessionBuilder<'a>) -> ts::SessionBuilder<'a> {
+        builder
+            .nearest_neighbors(self.nearest_neighbors)
+            .random_sample_locations(self.random_sample_locations)
+            .backtrack_stages(self.backtrack_stages)
+            .backtrack_percent(self.backtrack_percent)
+            .cauchy_dispersion(self.cauchy_dispersion)
+            .guide_alpha(self.guide_alpha)
+    }
+}
+
+impl Default for SynthesisParams {
+    fn default() -> SynthesisParams {
+        SynthesisParams::preset(Quality::Balanced)
+    }
+}
@@ -1,22 +1,37 @@
+use crate::error::Error;
+use crate::{TileLayout, WTileSet};
 use std::sync::Arc;
 /// omega-tile
 use texture_synthesis as ts;
-use ts::image::DynamicImage;
+use ts::image::{DynamicImage, GenericImageView};
 
-#[derive(Clone, Debug, Copy)]
-pub(crate) enum Direction {
+/// Side of a tile
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub enum Direction {
     North = 0,
     East = 1,
     South = 2,
     West = 3,
 }
 
+impl Direction {
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::North => Direction::South,
+            Direction::East => Direction::West,
+            Direction::South => Direction::North,
+            Direction::West => Direction::East,
+        }
+    }
+}
+
+/// Colors at both ends of an edge
 #[derive(Clone, Debug)]
-pub(crate) struct Edge(Direction, (usize, usize));
+pub(crate) struct Edge((usize, usize));
 
 impl Edge {
     pub fn is_match(&self, other: &Edge) -> bool {
-        self.1 == other.1
+        self.0 == other.0
     }
 }
 
@@ -27,28 +42,84 @@ pub struct WTile {
 }
 
 impl WTile {
+    /// Tile with the corner colors (a, b, c, d), from top left to bottom right.
+    ///
+    /// Colors are between 0 and 3, tiles sharing an edge must have the same
+    /// colors at both ends of it.
     pub fn new(img: DynamicImage, a: usize, b: usize, c: usize, d: usize) -> WTile {
-        use Direction::*;
+        // Indexed by `Direction`
         WTile {
             img: Arc::new(img),
-            edges: [
-                Edge(North, (a, b)),
-                Edge(East, (b, d)),
-                Edge(South, (c, d)),
-                Edge(West, (a, c)),
-            ],
+            edges: [Edge((a, b)), Edge((b, d)), Edge((c, d)), Edge((a, c))],
         }
     }
 
-    pub(crate) fn is_connectable(&self, dir: Direction, other: &WTile) -> bool {
-        let e: &Edge = match dir {
-            Direction::North => &other.edges[Direction::South as usize],
-            Direction::East => &other.edges[Direction::West as usize],
-            Direction::South => &other.edges[Direction::North as usize],
-            Direction::West => &other.edges[Direction::East as usize],
-        };
+    /// Corner colors (a, b, c, d)
+    pub fn corners(&self) -> [usize; 4] {
+        let (a, b) = self.edges[Direction::North as usize].0;
+        let (c, d) = self.edges[Direction::South as usize].0;
+        [a, b, c, d]
+    }
+
+    /// Colors at both ends of an edge, from left to right or from top to bottom
+    pub fn edge(&self, dir: Direction) -> (usize, usize) {
+        self.edges[dir as usize].0
+    }
 
+    /// Base 4 code of the corner colors, between 0 and 255.
+    ///
+    /// It is not the tile id used by [`FinishedTile`](crate::FinishedTile), the
+    /// quality reports, the atlas and the CLI, which is the index of the tile in
+    /// its set. Both only agree in the `Full` set, while the code of a tile is the
+    /// same in every variation.
+    pub fn corner_code(&self) -> usize {
+        let [a, b, c, d] = self.corners();
+        ((a * 4 + b) * 4 + c) * 4 + d
+    }
+
+    /// Whether `other` can be placed next to this tile, in direction `dir`
+    pub fn is_connectable(&self, dir: Direction, other: &WTile) -> bool {
+        let e: &Edge = &other.edges[dir.opposite() as usize];
         let my_edge = &self.edges[dir as usize];
-        e.is_match(&my_edge)
+        e.is_match(my_edge)
+    }
+}
+
+/// Check that a set has tiles and that their colors are between 0 and 3
+pub(crate) fn check_corners(corners: &[[usize; 4]]) -> Result<(), Error> {
+    if corners.is_empty() {
+        return Err(Error::InvalidInput("The tile set has no tile".to_string()));
+    }
+    match corners.iter().find(|it| it.iter().any(|&c| c > 3)) {
+        Some(it) => {
+            Err(Error::InvalidInput(format!("Corner colors are between 0 and 3, got {:?}", it)))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Make the tiles of `layout` from existing images, given in the order of its
+/// corners, see [`TileLayout::corners`]
+pub fn tiles_from_images(imgs: Vec<DynamicImage>, layout: &TileLayout) -> Result<WTileSet, Error> {
+    let corners = layout.corners();
+    if imgs.len() != corners.len() {
+        return Err(Error::InvalidInput(format!(
+            "The {} tile set needs {} tiles, got {}",
+            layout,
+            corners.len(),
+            imgs.len()
+        )));
     }
+    check_corners(&corners)?;
+    if let Some(first) = imgs.first() {
+        if imgs.iter().any(|it| it.dimensions() != first.dimensions()) {
+            return Err(Error::SizeMismatch);
+        }
+    }
+
+    Ok(imgs
+        .into_iter()
+        .zip(corners)
+        .map(|(img, [a, b, c, d])| WTile::new(img, a, b, c, d))
+        .collect())
 }
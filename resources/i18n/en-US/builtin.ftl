@@ -2,4 +2,9 @@ menu-file-menu = File
 menu-file-open = Open File...
 menu-file-exit = Exit
 
+menu-quality-menu = Quality
+menu-quality-fast = Fast
+menu-quality-balanced = Balanced
+menu-quality-high = High
+
 omega-tile-app-name = Omega-Tile Generator
\ No newline at end of file